@@ -0,0 +1,70 @@
+// Mesh of a section, see `SectionInfo` in `render/geometry.rs`.
+struct SectionInfo {
+    // World position of the origin of the chunk the section belongs to.
+    chunk_origin: vec3<i32>,
+    // Zero if the slot is free.
+    index_count: u32,
+    base_vertex: i32,
+    section: u32,
+}
+
+// Arguments of an indexed indirect draw, as expected by wgpu.
+struct DrawIndexedIndirect {
+    index_count: u32,
+    instance_count: u32,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32,
+}
+
+struct Frustum {
+    // Planes as (normal, distance), with the normal pointing inside.
+    planes: array<vec4<f32>, 6>,
+    // Origin the planes are relative to.
+    origin: vec3<i32>,
+}
+
+// Height of a section, in blocks.
+const SECTION_SIZE: f32 = 16.0;
+
+@group(0) @binding(0)
+var<uniform> frustum: Frustum;
+@group(0) @binding(1)
+var<storage, read> sections: array<SectionInfo>;
+@group(0) @binding(2)
+var<storage, read_write> draws: array<DrawIndexedIndirect>;
+
+fn is_visible(min: vec3<f32>, max: vec3<f32>) -> bool {
+    for (var i = 0u; i < 6u; i++) {
+        let plane = frustum.planes[i];
+        // The corner furthest along the normal is the last one to leave the
+        // frustum.
+        let corner = select(min, max, plane.xyz >= vec3<f32>(0.0));
+        if dot(plane.xyz, corner) + plane.w < 0.0 {
+            return false;
+        }
+    }
+    return true;
+}
+
+// Writes one draw per section slot, drawing no instance of the sections that
+// are free or outside of the frustum.
+@compute @workgroup_size(64)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let slot = id.x;
+    if slot >= arrayLength(&sections) {
+        return;
+    }
+    let info = sections[slot];
+    let chunk_origin = vec3<f32>(info.chunk_origin - frustum.origin);
+    let min = chunk_origin + vec3<f32>(0.0, f32(info.section) * SECTION_SIZE, 0.0);
+    let visible = info.index_count > 0u && is_visible(min, min + vec3<f32>(SECTION_SIZE));
+
+    var draw: DrawIndexedIndirect;
+    draw.index_count = info.index_count;
+    draw.instance_count = select(0u, 1u, visible);
+    draw.first_index = 0u;
+    draw.base_vertex = info.base_vertex;
+    draw.first_instance = slot;
+    draws[slot] = draw;
+}
@@ -0,0 +1,31 @@
+struct Uniforms {
+    proj: mat4x4<f32>,
+    // Relative to `origin`.
+    view: mat4x4<f32>,
+    // Origin of the chunk the camera is in.
+    origin: vec3<i32>,
+    atlas_size: u32,
+    atlas_tile_size: u32,
+}
+
+@group(0) @binding(0)
+var<uniform> uniforms: Uniforms;
+
+struct OutlineUniforms {
+    // World position of the outlined block.
+    offset: vec3<i32>,
+}
+
+@group(1) @binding(0)
+var<uniform> outline: OutlineUniforms;
+
+@vertex
+fn vs_main(@location(0) pos: vec3<f32>) -> @builtin(position) vec4<f32> {
+    let offset = vec3<f32>(outline.offset - uniforms.origin);
+    return uniforms.proj * uniforms.view * vec4<f32>(offset + pos, 1.0);
+}
+
+@fragment
+fn fs_main() -> @location(0) vec4<f32> {
+    return vec4<f32>(0.0, 0.0, 0.0, 1.0);
+}
@@ -1,6 +1,9 @@
 struct Uniforms {
     proj: mat4x4<f32>,
+    // Relative to `origin`.
     view: mat4x4<f32>,
+    // Origin of the chunk the camera is in.
+    origin: vec3<i32>,
     atlas_size: u32,
     atlas_tile_size: u32,
 }
@@ -8,52 +11,99 @@ struct Uniforms {
 @group(0) @binding(0)
 var<uniform> uniforms: Uniforms;
 
+// Mesh of a section, see `SectionInfo` in `render/geometry.rs`.
+struct SectionInfo {
+    // World position of the origin of the chunk the section belongs to.
+    chunk_origin: vec3<i32>,
+    index_count: u32,
+    base_vertex: i32,
+    section: u32,
+}
+
+// Sections sharing a vertex buffer, indexed by the instance drawn.
+@group(1) @binding(0)
+var<storage, read> sections: array<SectionInfo>;
+
+// Packed vertex, see `Vertex` in `render/mod.rs` for the layout.
 struct VertexIn {
-    @location(0) vertex_pos: vec3<f32>,
-    @location(1) texture_id: u32,
-    @builtin(vertex_index) v_index: u32
+    @location(0) data0: u32,
+    @location(1) data1: u32,
+}
+
+struct Vertex {
+    // Position relative to the chunk origin.
+    pos: vec3<f32>,
+    // Index of the face the vertex belongs to.
+    normal: u32,
+    // Corner of the quad, counter-clockwise from the top left one.
+    corner: u32,
+    ao: u32,
+    texture_id: u32,
+    // Dimensions of the quad, in blocks.
+    size: vec2<f32>,
+}
+
+fn unpack_vertex(in: VertexIn) -> Vertex {
+    var v: Vertex;
+    v.pos = vec3<f32>(
+        f32(in.data0 & 0x1fu),
+        f32((in.data0 >> 5u) & 0x1ffu),
+        f32((in.data0 >> 14u) & 0x1fu),
+    );
+    v.normal = (in.data0 >> 19u) & 0x7u;
+    v.corner = (in.data0 >> 22u) & 0x3u;
+    v.ao = (in.data0 >> 24u) & 0x3u;
+    v.texture_id = in.data1 & 0xffffu;
+    v.size = vec2<f32>(
+        f32(((in.data1 >> 16u) & 0xffu) + 1u),
+        f32(((in.data1 >> 24u) & 0xffu) + 1u),
+    );
+    return v;
+}
+
+// Texture coordinates of a quad corner, in tiles.
+fn corner_uv(corner: u32, size: vec2<f32>) -> vec2<f32> {
+    switch (corner) {
+        // top left
+        case 0u: { return vec2<f32>(0.0, 0.0); }
+        // bottom left
+        case 1u: { return vec2<f32>(0.0, size.y); }
+        // bottom right
+        case 2u: { return size; }
+        // top right
+        default: { return vec2<f32>(size.x, 0.0); }
+    }
 }
 
 struct VertexOut {
     @builtin(position) vertex_pos: vec4<f32>,
-    @location(0) tex_coords: vec2<f32>
+    @location(0) uv: vec2<f32>,
+    @location(1) @interpolate(flat) texture_id: u32,
+    @location(2) light: f32,
 }
 
-fn calculate_texture_coordinates(v_index: u32, texture_id: u32) -> vec2<f32> {
-    let tile_width = uniforms.atlas_tile_size;
-    let tile_height = uniforms.atlas_tile_size;
-    let tiles_per_row = uniforms.atlas_size / tile_width; 
-    let pixel_x = f32((texture_id % tiles_per_row) * tile_width);
-    let pixel_y = f32((texture_id / tiles_per_row) * tile_height);
-    switch (v_index % 4u) {
-          case 0u: {
-            // top left
-            return vec2<f32>(pixel_x / f32(uniforms.atlas_size), pixel_y / f32(uniforms.atlas_size));
-          }
-          case 1u: {
-            // bottom left
-            return vec2<f32>(pixel_x / f32(uniforms.atlas_size), (pixel_y + f32(tile_height)) / f32(uniforms.atlas_size));
-          }
-          case 2u: {
-            // bottom right
-            return vec2<f32>((pixel_x + f32(tile_width)) / f32(uniforms.atlas_size), (pixel_y + f32(tile_height)) / f32(uniforms.atlas_size));
-          }
-          case 3u: {
-            // top right
-            return vec2<f32>((pixel_x + f32(tile_width)) / f32(uniforms.atlas_size), pixel_y / f32(uniforms.atlas_size));
-          }
-          default: {
-              return vec2<f32>(0.0, 0.0);
-          }
-      }
+// Brightness of a fully occluded corner.
+const MIN_AO_LIGHT: f32 = 0.35;
 
+// Maps texture coordinates (in tiles) to atlas coordinates, repeating the tile
+// every unit so merged quads are tiled instead of stretched.
+fn calculate_texture_coordinates(uv: vec2<f32>, texture_id: u32) -> vec2<f32> {
+    let tiles_per_row = uniforms.atlas_size / uniforms.atlas_tile_size;
+    let tile = vec2<f32>(f32(texture_id % tiles_per_row), f32(texture_id / tiles_per_row));
+    return (tile + fract(uv)) * f32(uniforms.atlas_tile_size) / f32(uniforms.atlas_size);
 }
 
 @vertex
-fn vs_main(in: VertexIn) -> VertexOut{
+fn vs_main(in: VertexIn, @builtin(instance_index) slot: u32) -> VertexOut{
+    let v = unpack_vertex(in);
+    // Made relative to the camera before converting it, so that it stays
+    // precise far away from the origin of the world.
+    let offset = vec3<f32>(sections[slot].chunk_origin - uniforms.origin);
     var out: VertexOut;
-    out.vertex_pos = uniforms.proj * uniforms.view * vec4<f32>(in.vertex_pos, 1.0);
-    out.tex_coords = calculate_texture_coordinates(in.v_index, in.texture_id);
+    out.vertex_pos = uniforms.proj * uniforms.view * vec4<f32>(offset + v.pos, 1.0);
+    out.uv = corner_uv(v.corner, v.size);
+    out.texture_id = v.texture_id;
+    out.light = mix(MIN_AO_LIGHT, 1.0, f32(v.ao) / 3.0);
     return out;
 }
 
@@ -64,5 +114,7 @@ var texture_sampler: sampler;
 
 @fragment
 fn fs_main(in: VertexOut) -> @location(0) vec4<f32> {
-    return textureSample(texture, texture_sampler, in.tex_coords);
+    let tex_coords = calculate_texture_coordinates(in.uv, in.texture_id);
+    let color = textureSample(texture, texture_sampler, tex_coords);
+    return vec4<f32>(color.rgb * in.light, color.a);
 }
@@ -1,17 +1,268 @@
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
-pub enum BlockId {
-    Air,
-    Dirt,
-    Grass,
-    Stone,
-}
+use std::{collections::HashMap, path::Path};
+
+use serde::Deserialize;
+
+/// Numeric identifier of a kind of block.
+///
+/// Ids are assigned by the [BlockRegistry] when the block definitions are
+/// loaded, except for [`BlockId::AIR`] which is always available.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BlockId(u16);
 
 impl BlockId {
+    pub const AIR: Self = Self(0);
+
+    pub const fn from_raw(id: u16) -> Self {
+        Self(id)
+    }
+
+    pub const fn raw(self) -> u16 {
+        self.0
+    }
+
     pub const fn is_air(self) -> bool {
-        matches!(self, Self::Air)
+        self.0 == Self::AIR.0
+    }
+}
+
+/// Names of the textures used by each face of a block.
+#[derive(Clone, Debug, Deserialize)]
+pub enum BlockTextures {
+    /// No textures, the block is never drawn.
+    None,
+    /// The same texture on every face.
+    All(String),
+    /// One texture for the top, one for the bottom and one for the sides.
+    Sides {
+        top: String,
+        side: String,
+        bottom: String,
+    },
+    /// One texture per face.
+    Faces {
+        north: String,
+        south: String,
+        east: String,
+        west: String,
+        top: String,
+        bottom: String,
+    },
+}
+
+impl BlockTextures {
+    /// Returns the texture names in face order: north, south, east, west, top
+    /// and bottom.
+    pub fn faces(&self) -> Option<[&str; 6]> {
+        match self {
+            Self::None => None,
+            Self::All(all) => Some([all, all, all, all, all, all]),
+            Self::Sides { top, side, bottom } => Some([side, side, side, side, top, bottom]),
+            Self::Faces {
+                north,
+                south,
+                east,
+                west,
+                top,
+                bottom,
+            } => Some([north, south, east, west, top, bottom]),
+        }
+    }
+}
+
+/// Properties of a kind of block, as loaded from its definition file.
+#[derive(Clone, Debug, Deserialize)]
+pub struct BlockDef {
+    /// Unique name of the block, taken from its file name.
+    #[serde(skip)]
+    pub name: String,
+    /// Whether entities collide with the block.
+    pub solid: bool,
+    /// Whether the block hides the faces of the blocks behind it.
+    pub opaque: bool,
+    pub textures: BlockTextures,
+    /// How long the block takes to break. Negative values make it unbreakable.
+    #[serde(default)]
+    pub hardness: f32,
+    /// Amount of light emitted by the block, from 0 to 15.
+    #[serde(default)]
+    pub light: u8,
+}
+
+impl BlockDef {
+    fn air() -> Self {
+        Self {
+            name: "air".to_owned(),
+            solid: false,
+            opaque: false,
+            textures: BlockTextures::None,
+            hardness: 0.0,
+            light: 0,
+        }
+    }
+}
+
+#[cfg(any(test, feature = "test-utils"))]
+impl BlockDef {
+    /// A solid and opaque block without textures.
+    pub fn test(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            solid: true,
+            opaque: true,
+            textures: BlockTextures::None,
+            hardness: 0.0,
+            light: 0,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum BlockRegistryError {
+    Io(std::io::ErrorKind),
+    Parse {
+        name: String,
+        error: ron::error::SpannedError,
+    },
+    TooManyBlocks,
+    /// A block id table names a block that is not defined.
+    UnknownBlock(String),
+    /// A block id table does not start with air, or names a block twice.
+    InvalidIdTable,
+}
+
+impl From<std::io::Error> for BlockRegistryError {
+    fn from(value: std::io::Error) -> Self {
+        BlockRegistryError::Io(value.kind())
+    }
+}
+
+/// All the kinds of blocks known to the game.
+///
+/// Blocks are defined in `.ron` files, one per block, named after the block.
+/// Ids are assigned in alphabetical order of names after air, so every
+/// instance of the game loading the same definitions agrees on them.
+pub struct BlockRegistry {
+    blocks: Vec<BlockDef>,
+    ids: HashMap<String, BlockId>,
+}
+
+impl BlockRegistry {
+    /// Loads every block definition found in the given directory.
+    pub fn load<P: AsRef<Path>>(dir: P) -> Result<Self, BlockRegistryError> {
+        let mut files = std::fs::read_dir(dir)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        files.retain(|path| path.extension().is_some_and(|ext| ext == "ron"));
+        files.sort();
+
+        let mut defs = Vec::with_capacity(files.len());
+        for path in files {
+            let name = path.file_stem().unwrap().to_string_lossy().into_owned();
+            let source = std::fs::read_to_string(&path)?;
+            let mut def: BlockDef =
+                ron::from_str(&source).map_err(|error| BlockRegistryError::Parse {
+                    name: name.clone(),
+                    error,
+                })?;
+            def.name = name;
+            defs.push(def);
+        }
+        Self::from_defs(defs)
+    }
+
+    /// Creates a registry from the given definitions, in addition to air.
+    pub fn from_defs(defs: Vec<BlockDef>) -> Result<Self, BlockRegistryError> {
+        let mut blocks = vec![BlockDef::air()];
+        blocks.extend(defs.into_iter().filter(|def| def.name != "air"));
+        if blocks.len() > u16::MAX as usize {
+            return Err(BlockRegistryError::TooManyBlocks);
+        }
+        let ids = blocks
+            .iter()
+            .enumerate()
+            .map(|(id, def)| (def.name.clone(), BlockId(id as u16)))
+            .collect();
+        tracing::info!("Loaded {} block definitions.", blocks.len());
+        Ok(Self { blocks, ids })
+    }
+
+    /// Creates a registry with the same definitions, numbered after the given
+    /// id table: the block named `names[i]` gets id `i`. Blocks the table does
+    /// not name get the ids following it, in their current order.
+    ///
+    /// Fails if the table names a block that is not defined, as there would
+    /// be no definition for its id.
+    pub fn remap(&self, names: &[String]) -> Result<Self, BlockRegistryError> {
+        if names.first().is_some_and(|name| name != "air") {
+            return Err(BlockRegistryError::InvalidIdTable);
+        }
+        let mut blocks = Vec::with_capacity(self.blocks.len().max(names.len()));
+        let mut ids = HashMap::with_capacity(blocks.capacity());
+        let remapped = names
+            .iter()
+            .map(|name| {
+                self.id(name)
+                    .ok_or_else(|| BlockRegistryError::UnknownBlock(name.clone()))
+            })
+            .chain(self.iter().map(|(id, _)| Ok(id)));
+        for id in remapped {
+            let def = self.get(id?);
+            if ids.contains_key(&def.name) {
+                // Either named twice by the table, or already placed by it.
+                if blocks.len() < names.len() {
+                    return Err(BlockRegistryError::InvalidIdTable);
+                }
+                continue;
+            }
+            if blocks.len() > u16::MAX as usize {
+                return Err(BlockRegistryError::TooManyBlocks);
+            }
+            ids.insert(def.name.clone(), BlockId(blocks.len() as u16));
+            blocks.push(def.clone());
+        }
+        Ok(Self { blocks, ids })
+    }
+
+    /// Returns the names of every block in id order, including air, as
+    /// accepted by [`BlockRegistry::remap`].
+    pub fn names(&self) -> Vec<String> {
+        self.blocks.iter().map(|def| def.name.clone()).collect()
+    }
+
+    /// Returns the id of the block with the given name.
+    pub fn id(&self, name: &str) -> Option<BlockId> {
+        self.ids.get(name).copied()
+    }
+
+    /// Returns the definition of the given block.
+    ///
+    /// Unknown ids are treated as air.
+    pub fn get(&self, id: BlockId) -> &BlockDef {
+        self.blocks.get(id.0 as usize).unwrap_or(&self.blocks[0])
+    }
+
+    pub fn is_solid(&self, id: BlockId) -> bool {
+        self.get(id).solid
+    }
+
+    pub fn is_opaque(&self, id: BlockId) -> bool {
+        self.get(id).opaque
+    }
+
+    /// Iterates over every block, including air, in id order.
+    pub fn iter(&self) -> impl Iterator<Item = (BlockId, &BlockDef)> {
+        self.blocks
+            .iter()
+            .enumerate()
+            .map(|(id, def)| (BlockId(id as u16), def))
+    }
+
+    /// Number of known blocks, including air.
+    pub fn len(&self) -> usize {
+        self.blocks.len()
     }
 
-    pub const fn is_solid(self) -> bool {
-        !self.is_air()
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
     }
 }
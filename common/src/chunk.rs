@@ -1,30 +1,38 @@
-use crate::{block::BlockId, math::Vec3};
+use crate::{block::BlockId, math::Vec3, palette::PalettedStorage};
 
-pub struct Chunk {
-    blocks: [BlockId; Self::SIZE.x * Self::SIZE.y * Self::SIZE.z],
+/// A 16x16x16 cube of blocks, the vertical unit chunks are split in.
+///
+/// Sections are stored independently so edits, meshing and transfers can
+/// work on a small part of a chunk instead of the whole column.
+#[derive(Clone, Debug)]
+pub struct Section {
+    blocks: PalettedStorage,
 }
 
-impl Chunk {
-    pub const SIZE: Vec3<usize> = Vec3::new(16, 256, 16);
+impl Section {
+    pub const SIZE: Vec3<usize> = Vec3::new(16, 16, 16);
+    /// Number of blocks in a section.
+    pub const VOLUME: usize = Self::SIZE.x * Self::SIZE.y * Self::SIZE.z;
 
-    pub fn flat() -> Self {
-        let mut blocks = [BlockId::Air; Self::SIZE.x * Self::SIZE.y * Self::SIZE.z];
-        for x in 0..Self::SIZE.x {
-            for y in 0..Self::SIZE.y {
-                for z in 0..Self::SIZE.z {
-                    let index = Self::index(Vec3::new(x as i32, y as i32, z as i32)).unwrap();
-                    blocks[index] = match y {
-                        0..=32 => BlockId::Stone,
-                        33..=254 => BlockId::Dirt,
-                        255 => BlockId::Grass,
-                        _ => BlockId::Air,
-                    };
-                }
-            }
-        }
+    /// Creates a section filled with air.
+    pub fn empty() -> Self {
+        Self::from_storage(PalettedStorage::new(Self::VOLUME, BlockId::AIR))
+    }
+
+    /// Creates a section from its block storage.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the storage does not have exactly [`Section::VOLUME`] blocks.
+    pub fn from_storage(blocks: PalettedStorage) -> Self {
+        assert_eq!(blocks.len(), Self::VOLUME);
         Self { blocks }
     }
 
+    pub fn storage(&self) -> &PalettedStorage {
+        &self.blocks
+    }
+
     pub fn index(pos: Vec3<i32>) -> Option<usize> {
         if pos.is_any_negative() {
             return None;
@@ -37,10 +45,90 @@ impl Chunk {
     }
 
     pub fn get(&self, pos: Vec3<i32>) -> Option<BlockId> {
-        if pos.is_any_negative() {
-            return None;
+        Self::index(pos).map(|index| self.blocks.get(index))
+    }
+
+    /// Sets the block at the given position, returning the previous one.
+    ///
+    /// Returns `None` and does nothing if the position is out of bounds.
+    pub fn set(&mut self, pos: Vec3<i32>, block: BlockId) -> Option<BlockId> {
+        Self::index(pos).map(|index| self.blocks.set(index, block))
+    }
+
+    /// Whether the section only contains air.
+    ///
+    /// Sections are only known to be empty if they were never edited or
+    /// have been compacted since.
+    pub fn is_empty(&self) -> bool {
+        self.blocks.single() == Some(BlockId::AIR)
+    }
+
+    /// See [`PalettedStorage::compact`].
+    pub fn compact(&mut self) {
+        self.blocks.compact();
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Chunk {
+    /// Sections from the bottom to the top of the chunk.
+    sections: [Section; Self::SECTIONS],
+}
+
+impl Chunk {
+    pub const SIZE: Vec3<usize> = Vec3::new(16, 256, 16);
+    /// Number of blocks in a chunk.
+    pub const VOLUME: usize = Self::SIZE.x * Self::SIZE.y * Self::SIZE.z;
+    /// Number of sections stacked in a chunk.
+    pub const SECTIONS: usize = Self::SIZE.y / Section::SIZE.y;
+
+    /// Creates a chunk filled with air.
+    pub fn empty() -> Self {
+        Self {
+            sections: std::array::from_fn(|_| Section::empty()),
+        }
+    }
+
+    /// Creates a chunk from its sections, from the bottom to the top.
+    pub fn from_sections(sections: [Section; Self::SECTIONS]) -> Self {
+        Self { sections }
+    }
+
+    pub fn sections(&self) -> &[Section; Self::SECTIONS] {
+        &self.sections
+    }
+
+    pub fn section(&self, index: usize) -> Option<&Section> {
+        self.sections.get(index)
+    }
+
+    /// Returns the index of the section containing the given height.
+    pub fn section_index(y: i32) -> Option<usize> {
+        (0..Self::SIZE.y as i32)
+            .contains(&y)
+            .then_some(y as usize / Section::SIZE.y)
+    }
+
+    pub fn get(&self, pos: Vec3<i32>) -> Option<BlockId> {
+        let (section, local) = Self::split_pos(pos)?;
+        self.sections[section].get(local)
+    }
+
+    /// Sets the block at the given position, returning the previous one.
+    ///
+    /// Returns `None` and does nothing if the position is out of bounds.
+    pub fn set(&mut self, pos: Vec3<i32>, block: BlockId) -> Option<BlockId> {
+        let (section, local) = Self::split_pos(pos)?;
+        self.sections[section].set(local, block)
+    }
+
+    /// Reduces the memory used by the chunk after a batch of edits.
+    ///
+    /// See [`PalettedStorage::compact`].
+    pub fn compact(&mut self) {
+        for section in &mut self.sections {
+            section.compact();
         }
-        Self::index(pos).map(|index| self.blocks[index])
     }
 
     pub fn out_of_bounds(pos: Vec3<i32>) -> bool {
@@ -49,4 +137,15 @@ impl Chunk {
             || pos.y >= Self::SIZE.y as i32
             || pos.z >= Self::SIZE.z as i32
     }
+
+    /// Converts a position within the chunk into a section index and a
+    /// position within that section.
+    fn split_pos(pos: Vec3<i32>) -> Option<(usize, Vec3<i32>)> {
+        if Self::out_of_bounds(pos) {
+            return None;
+        }
+        let section = pos.y as usize / Section::SIZE.y;
+        let local = Vec3::new(pos.x, pos.y % Section::SIZE.y as i32, pos.z);
+        Some((section, local))
+    }
 }
@@ -0,0 +1,387 @@
+//! Binary encoding of chunks, shared by world saves and the network protocol.
+//!
+//! An encoded chunk starts with a fixed size header, followed by the body
+//! holding its sections from the bottom to the top. All the numbers are
+//! little-endian.
+//!
+//! | Field       | Type       | Notes                                 |
+//! |-------------|------------|---------------------------------------|
+//! | magic       | `[u8; 4]`  | [MAGIC]                               |
+//! | version     | `u16`      | [FORMAT_VERSION]                      |
+//! | compression | `u8`       | See [Compression]                     |
+//! | x, z        | `i32, i32` | Position of the chunk                 |
+//! | body length | `u32`      | Size of the body before compression   |
+//! | body        |            | Compressed as stated in the header    |
+//!
+//! Each section of the body is written as its [PalettedStorage] parts:
+//!
+//! | Field         | Type            | Notes                            |
+//! |---------------|-----------------|----------------------------------|
+//! | palette size  | `u16`           |                                  |
+//! | palette       | `[u16]`         | Raw [BlockId]s                   |
+//! | bits          | `u8`            | Bits per index                   |
+//! | data          | `[u64]`         | Packed indices, length implied   |
+
+use crate::{
+    block::BlockId,
+    chunk::{Chunk, Section},
+    math::Vec2,
+    palette::{self, PalettedStorage},
+};
+
+/// Identifies encoded chunks.
+pub const MAGIC: [u8; 4] = *b"EXCK";
+/// Version of the encoding written by [encode_chunk].
+///
+/// Must be bumped on any change to the layout.
+pub const FORMAT_VERSION: u16 = 1;
+
+const HEADER_LEN: usize = 4 + 2 + 1 + 4 + 4 + 4;
+/// Largest possible body: every section with a full palette and 16 bits per
+/// index.
+const MAX_BODY_LEN: usize = Chunk::SECTIONS * (2 + 2 * (1 << 16) + 1 + 2 * Section::VOLUME);
+
+/// How the body of an encoded chunk is compressed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Compression {
+    #[default]
+    None,
+    /// LZ4 block compression.
+    Lz4,
+}
+
+impl Compression {
+    fn to_raw(self) -> u8 {
+        match self {
+            Self::None => 0,
+            Self::Lz4 => 1,
+        }
+    }
+
+    fn from_raw(raw: u8) -> Option<Self> {
+        match raw {
+            0 => Some(Self::None),
+            1 => Some(Self::Lz4),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ChunkCodecError {
+    /// The data ended before the chunk did.
+    UnexpectedEof,
+    /// The data does not start with [MAGIC].
+    InvalidMagic,
+    UnsupportedVersion(u16),
+    UnknownCompression(u8),
+    /// The body could not be decompressed, or is not as long as the header
+    /// says.
+    InvalidBody,
+    /// The section at the given index is not valid storage.
+    InvalidSection(usize),
+    /// There is data left after the chunk.
+    TrailingData,
+}
+
+/// Encodes the chunk at the given position.
+pub fn encode_chunk(pos: Vec2<i32>, chunk: &Chunk, compression: Compression) -> Vec<u8> {
+    let mut body = Vec::new();
+    for section in chunk.sections() {
+        let storage = section.storage();
+        body.extend_from_slice(&(storage.palette().len() as u16).to_le_bytes());
+        for block in storage.palette() {
+            body.extend_from_slice(&block.raw().to_le_bytes());
+        }
+        body.push(storage.bits() as u8);
+        for word in storage.data() {
+            body.extend_from_slice(&word.to_le_bytes());
+        }
+    }
+
+    let mut bytes = Vec::with_capacity(HEADER_LEN + body.len());
+    bytes.extend_from_slice(&MAGIC);
+    bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    bytes.push(compression.to_raw());
+    bytes.extend_from_slice(&pos.x.to_le_bytes());
+    bytes.extend_from_slice(&pos.y.to_le_bytes());
+    bytes.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    match compression {
+        Compression::None => bytes.extend_from_slice(&body),
+        Compression::Lz4 => bytes.extend_from_slice(&lz4_flex::block::compress(&body)),
+    }
+    bytes
+}
+
+/// Size of the body of the chunk before compression, a cheap estimate of
+/// how large [encode_chunk] makes it.
+pub fn body_len(chunk: &Chunk) -> usize {
+    chunk
+        .sections()
+        .iter()
+        .map(|section| {
+            let storage = section.storage();
+            2 + storage.palette().len() * 2 + 1 + storage.data().len() * 8
+        })
+        .sum()
+}
+
+/// Decodes a chunk written by [encode_chunk], returning its position and the
+/// chunk itself.
+pub fn decode_chunk(bytes: &[u8]) -> Result<(Vec2<i32>, Chunk), ChunkCodecError> {
+    let mut reader = Reader::new(bytes);
+    if reader.take(MAGIC.len())? != MAGIC {
+        return Err(ChunkCodecError::InvalidMagic);
+    }
+    let version = reader.u16()?;
+    if version != FORMAT_VERSION {
+        return Err(ChunkCodecError::UnsupportedVersion(version));
+    }
+    let compression = reader.u8()?;
+    let compression = Compression::from_raw(compression)
+        .ok_or(ChunkCodecError::UnknownCompression(compression))?;
+    let pos = Vec2::new(reader.i32()?, reader.i32()?);
+    let body_len = reader.u32()? as usize;
+    if body_len > MAX_BODY_LEN {
+        return Err(ChunkCodecError::InvalidBody);
+    }
+
+    let rest = reader.take(reader.remaining())?;
+    let decompressed;
+    let body = match compression {
+        Compression::None => rest,
+        Compression::Lz4 => {
+            decompressed = lz4_flex::block::decompress(rest, body_len)
+                .map_err(|_| ChunkCodecError::InvalidBody)?;
+            &decompressed
+        }
+    };
+    if body.len() != body_len {
+        return Err(ChunkCodecError::InvalidBody);
+    }
+
+    let mut reader = Reader::new(body);
+    let mut sections = Vec::with_capacity(Chunk::SECTIONS);
+    for index in 0..Chunk::SECTIONS {
+        sections.push(read_section(&mut reader).ok_or(ChunkCodecError::InvalidSection(index))?);
+    }
+    if reader.remaining() > 0 {
+        return Err(ChunkCodecError::TrailingData);
+    }
+    let sections = sections
+        .try_into()
+        .unwrap_or_else(|_| unreachable!("all the sections were read"));
+    Ok((pos, Chunk::from_sections(sections)))
+}
+
+fn read_section(reader: &mut Reader) -> Option<Section> {
+    let palette_len = reader.u16().ok()? as usize;
+    let palette = (0..palette_len)
+        .map(|_| reader.u16().map(BlockId::from_raw))
+        .collect::<Result<Vec<_>, _>>()
+        .ok()?;
+    let bits = reader.u8().ok()? as u32;
+    if bits > 16 {
+        return None;
+    }
+    let data = (0..palette::words_for(Section::VOLUME, bits))
+        .map(|_| reader.u64())
+        .collect::<Result<Vec<_>, _>>()
+        .ok()?;
+    PalettedStorage::from_raw_parts(Section::VOLUME, palette, bits, data).map(Section::from_storage)
+}
+
+/// The data ended before the value being read.
+#[derive(Debug)]
+pub(crate) struct UnexpectedEof;
+
+impl From<UnexpectedEof> for ChunkCodecError {
+    fn from(_: UnexpectedEof) -> Self {
+        ChunkCodecError::UnexpectedEof
+    }
+}
+
+/// Reads little-endian numbers from a slice.
+pub(crate) struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    pub(crate) fn remaining(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub(crate) fn take(&mut self, len: usize) -> Result<&'a [u8], UnexpectedEof> {
+        if self.bytes.len() < len {
+            return Err(UnexpectedEof);
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], UnexpectedEof> {
+        Ok(self.take(N)?.try_into().expect("took N bytes"))
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, UnexpectedEof> {
+        Ok(u8::from_le_bytes(self.array()?))
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16, UnexpectedEof> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, UnexpectedEof> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    pub(crate) fn i32(&mut self) -> Result<i32, UnexpectedEof> {
+        Ok(i32::from_le_bytes(self.array()?))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, UnexpectedEof> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    pub(crate) fn f32(&mut self) -> Result<f32, UnexpectedEof> {
+        Ok(f32::from_le_bytes(self.array()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        block::{BlockDef, BlockRegistry},
+        math::Vec3,
+        terrain::{HeightmapGenerator, WorldGenerator},
+    };
+
+    fn generated() -> Chunk {
+        let defs = ["dirt", "grass", "stone"].map(BlockDef::test);
+        let blocks = BlockRegistry::from_defs(defs.into()).unwrap();
+        HeightmapGenerator::new(1, &blocks).generate(Vec2::new(-4, 9))
+    }
+
+    /// A chunk whose palettes hold blocks that are not used anymore.
+    fn edited() -> Chunk {
+        let mut chunk = generated();
+        for (i, id) in (10..20).enumerate() {
+            chunk.set(Vec3::new(i as i32, 100, 3), BlockId::from_raw(id));
+        }
+        for i in 0..10 {
+            chunk.set(Vec3::new(i, 100, 3), BlockId::AIR);
+        }
+        let section = Chunk::section_index(100).unwrap();
+        assert!(chunk.sections()[section].storage().palette().len() > 10);
+        chunk
+    }
+
+    fn assert_same(a: &Chunk, b: &Chunk) {
+        for (a, b) in a.sections().iter().zip(b.sections()) {
+            let (a, b) = (a.storage(), b.storage());
+            assert_eq!(a.palette(), b.palette());
+            assert_eq!(a.bits(), b.bits());
+            assert_eq!(a.data(), b.data());
+        }
+    }
+
+    fn round_trip(pos: Vec2<i32>, chunk: &Chunk) {
+        for compression in [Compression::None, Compression::Lz4] {
+            let bytes = encode_chunk(pos, chunk, compression);
+            let (decoded_pos, decoded) = decode_chunk(&bytes).unwrap();
+            assert_eq!(decoded_pos, pos);
+            assert_same(chunk, &decoded);
+        }
+    }
+
+    #[test]
+    fn round_trips() {
+        round_trip(Vec2::new(0, 0), &Chunk::empty());
+        round_trip(Vec2::new(-4, 9), &generated());
+        round_trip(Vec2::new(i32::MIN, i32::MAX), &edited());
+    }
+
+    #[test]
+    fn body_len_matches_encoding() {
+        let chunk = edited();
+        let bytes = encode_chunk(Vec2::zero(), &chunk, Compression::None);
+        assert_eq!(bytes.len(), HEADER_LEN + body_len(&chunk));
+    }
+
+    #[test]
+    fn rejects_invalid_headers() {
+        let bytes = encode_chunk(Vec2::zero(), &generated(), Compression::Lz4);
+
+        let mut magic = bytes.clone();
+        magic[0] = b'X';
+        assert_eq!(
+            decode_chunk(&magic).err(),
+            Some(ChunkCodecError::InvalidMagic)
+        );
+
+        let mut version = bytes.clone();
+        version[4..6].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        assert_eq!(
+            decode_chunk(&version).err(),
+            Some(ChunkCodecError::UnsupportedVersion(FORMAT_VERSION + 1))
+        );
+
+        let mut compression = bytes.clone();
+        compression[6] = 9;
+        assert_eq!(
+            decode_chunk(&compression).err(),
+            Some(ChunkCodecError::UnknownCompression(9))
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_data() {
+        for compression in [Compression::None, Compression::Lz4] {
+            let bytes = encode_chunk(Vec2::zero(), &generated(), compression);
+            assert_eq!(
+                decode_chunk(&bytes[..HEADER_LEN - 1]).err(),
+                Some(ChunkCodecError::UnexpectedEof)
+            );
+            assert_eq!(
+                decode_chunk(&bytes[..bytes.len() - 1]).err(),
+                Some(ChunkCodecError::InvalidBody)
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_trailing_data() {
+        let mut bytes = encode_chunk(Vec2::zero(), &generated(), Compression::None);
+        let len = u32::from_le_bytes(bytes[15..19].try_into().unwrap());
+        bytes[15..19].copy_from_slice(&(len + 1).to_le_bytes());
+        bytes.push(0);
+        assert_eq!(
+            decode_chunk(&bytes).err(),
+            Some(ChunkCodecError::TrailingData)
+        );
+    }
+
+    #[test]
+    fn rejects_palette_indices_past_the_end() {
+        let mut chunk = Chunk::empty();
+        chunk.set(Vec3::new(1, 0, 0), BlockId::from_raw(1));
+        chunk.set(Vec3::new(2, 0, 0), BlockId::from_raw(2));
+        assert_eq!(chunk.sections()[0].storage().bits(), 2);
+
+        let mut bytes = encode_chunk(Vec2::zero(), &chunk, Compression::None);
+        // The palette of the first section holds 3 blocks, so index 3 is past
+        // its end.
+        let data = HEADER_LEN + 2 + 3 * 2 + 1;
+        bytes[data] |= 0b11;
+        assert_eq!(
+            decode_chunk(&bytes).err(),
+            Some(ChunkCodecError::InvalidSection(0))
+        );
+    }
+}
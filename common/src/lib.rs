@@ -1,3 +1,13 @@
 pub mod block;
 pub mod chunk;
+pub mod codec;
 pub mod math;
+pub mod net;
+pub mod palette;
+pub mod protocol;
+pub mod raycast;
+pub mod region;
+pub mod save;
+pub mod terrain;
+pub mod thread_pool;
+pub mod world;
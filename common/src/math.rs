@@ -1,3 +1,4 @@
 pub type Vec3<T> = vek::Vec3<T>;
 pub type Vec2<T> = vek::Vec2<T>;
+pub type Vec4<T> = vek::Vec4<T>;
 pub type Mat4f = vek::Mat4<f32>;
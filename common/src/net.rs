@@ -0,0 +1,214 @@
+//! Transports carrying [crate::protocol] messages.
+
+use std::{
+    io::{self, Read, Write},
+    marker::PhantomData,
+    net::{Shutdown, SocketAddr, TcpStream, ToSocketAddrs},
+    sync::mpsc::{self, TryRecvError},
+};
+
+use crate::protocol::{
+    encode_frame, ClientMessage, FrameDecoder, Message, ProtocolError, ServerMessage,
+};
+
+/// One end of a connection, sending messages of type `S` and receiving
+/// messages of type `R`.
+///
+/// Connections never block: messages are queued by [`Connection::send`] and
+/// actually written by [`Connection::flush`], and [`Connection::try_recv`]
+/// only returns the messages that have already arrived.
+pub trait Connection<S, R>: Send {
+    fn send(&mut self, message: S) -> Result<(), ProtocolError>;
+
+    /// Writes as many queued messages as possible.
+    ///
+    /// Connections may refuse to queue more messages while the ones already
+    /// queued are not written, see [`ProtocolError::QueueFull`].
+    fn flush(&mut self) -> Result<(), ProtocolError>;
+
+    /// Returns the next received message, if any.
+    fn try_recv(&mut self) -> Result<Option<R>, ProtocolError>;
+
+    /// Closes the connection. Messages still queued may be lost.
+    fn close(&mut self);
+
+    /// Describes the other end of the connection, for logging.
+    fn peer(&self) -> String;
+}
+
+/// The server end of a connection.
+pub type ServerConnection = dyn Connection<ServerMessage, ClientMessage>;
+/// The client end of a connection.
+pub type ClientConnection = dyn Connection<ClientMessage, ServerMessage>;
+
+/// Most bytes a [TcpConnection] queues before refusing to send more, so that
+/// a peer not reading its data cannot make the other end run out of memory.
+pub const MAX_QUEUED_BYTES: usize = 8 << 20;
+
+/// A connection over TCP.
+pub struct TcpConnection<S, R> {
+    stream: TcpStream,
+    peer: SocketAddr,
+    /// Encoded frames that could not be written yet.
+    outgoing: Vec<u8>,
+    incoming: FrameDecoder,
+    _messages: PhantomData<fn(S) -> R>,
+}
+
+impl<S, R> TcpConnection<S, R> {
+    /// Wraps a connected stream, switching it to non-blocking mode.
+    pub fn new(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+        Ok(Self {
+            peer: stream.peer_addr()?,
+            stream,
+            outgoing: Vec::new(),
+            incoming: FrameDecoder::new(),
+            _messages: PhantomData,
+        })
+    }
+
+    /// Connects to the given address, blocking until the connection is
+    /// established.
+    pub fn connect(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        Self::new(TcpStream::connect(addr)?)
+    }
+
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer
+    }
+
+    /// Number of bytes queued but not written yet.
+    pub fn pending_bytes(&self) -> usize {
+        self.outgoing.len()
+    }
+}
+
+impl<S: Message, R: Message> Connection<S, R> for TcpConnection<S, R> {
+    fn send(&mut self, message: S) -> Result<(), ProtocolError> {
+        if self.outgoing.len() > MAX_QUEUED_BYTES {
+            return Err(ProtocolError::QueueFull);
+        }
+        encode_frame(&mut self.outgoing, &message);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), ProtocolError> {
+        let mut written = 0;
+        while written < self.outgoing.len() {
+            match self.stream.write(&self.outgoing[written..]) {
+                Ok(0) => return Err(ProtocolError::ConnectionClosed),
+                Ok(n) => written += n,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        self.outgoing.drain(..written);
+        Ok(())
+    }
+
+    fn try_recv(&mut self) -> Result<Option<R>, ProtocolError> {
+        if let Some(message) = self.incoming.next_message()? {
+            return Ok(Some(message));
+        }
+        let mut buf = [0; 16 * 1024];
+        loop {
+            match self.stream.read(&mut buf) {
+                Ok(0) => return Err(ProtocolError::ConnectionClosed),
+                Ok(n) => {
+                    self.incoming.extend(&buf[..n]);
+                    if let Some(message) = self.incoming.next_message()? {
+                        return Ok(Some(message));
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    fn close(&mut self) {
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+
+    fn peer(&self) -> String {
+        self.peer.to_string()
+    }
+}
+
+/// A connection between two ends living in the same process.
+///
+/// Messages are encoded and decoded like over TCP, so both ends go through
+/// the same code paths as over a network, but frames are handed over through
+/// a channel.
+pub struct ChannelConnection<S, R> {
+    /// `None` once the connection is closed.
+    tx: Option<mpsc::Sender<Vec<u8>>>,
+    rx: mpsc::Receiver<Vec<u8>>,
+    incoming: FrameDecoder,
+    peer: String,
+    _messages: PhantomData<fn(S) -> R>,
+}
+
+impl<S, R> ChannelConnection<S, R> {
+    /// Creates the two ends of a connection, named after `peers`. Each end
+    /// reports the name of the other one as its [`Connection::peer`].
+    pub fn pair(peers: [&str; 2]) -> (Self, ChannelConnection<R, S>) {
+        let (a_tx, b_rx) = mpsc::channel();
+        let (b_tx, a_rx) = mpsc::channel();
+        let a = Self {
+            tx: Some(a_tx),
+            rx: a_rx,
+            incoming: FrameDecoder::new(),
+            peer: peers[1].to_owned(),
+            _messages: PhantomData,
+        };
+        let b = ChannelConnection {
+            tx: Some(b_tx),
+            rx: b_rx,
+            incoming: FrameDecoder::new(),
+            peer: peers[0].to_owned(),
+            _messages: PhantomData,
+        };
+        (a, b)
+    }
+}
+
+impl<S: Message, R: Message> Connection<S, R> for ChannelConnection<S, R> {
+    fn send(&mut self, message: S) -> Result<(), ProtocolError> {
+        let mut frame = Vec::new();
+        encode_frame(&mut frame, &message);
+        self.tx
+            .as_ref()
+            .and_then(|tx| tx.send(frame).ok())
+            .ok_or(ProtocolError::ConnectionClosed)
+    }
+
+    fn flush(&mut self) -> Result<(), ProtocolError> {
+        Ok(())
+    }
+
+    fn try_recv(&mut self) -> Result<Option<R>, ProtocolError> {
+        loop {
+            if let Some(message) = self.incoming.next_message()? {
+                return Ok(Some(message));
+            }
+            match self.rx.try_recv() {
+                Ok(frame) => self.incoming.extend(&frame),
+                Err(TryRecvError::Empty) => return Ok(None),
+                Err(TryRecvError::Disconnected) => return Err(ProtocolError::ConnectionClosed),
+            }
+        }
+    }
+
+    fn close(&mut self) {
+        self.tx = None;
+    }
+
+    fn peer(&self) -> String {
+        self.peer.clone()
+    }
+}
@@ -0,0 +1,343 @@
+use crate::block::BlockId;
+
+/// Compact storage for a fixed amount of blocks.
+///
+/// Instead of storing every [BlockId], blocks are stored as indices into a
+/// palette of the distinct blocks present, packed with as few bits as the
+/// palette size requires. Storage holding a single kind of block (e.g. all
+/// air) does not allocate any indices at all.
+///
+/// The palette grows as new blocks are set, but never shrinks on its own; see
+/// [`PalettedStorage::compact`].
+#[derive(Clone, Debug)]
+pub struct PalettedStorage {
+    len: usize,
+    palette: Vec<BlockId>,
+    /// Bits used by each index. 0 when there is a single block in the palette.
+    bits: u32,
+    /// Packed palette indices. Indices never straddle two words.
+    data: Vec<u64>,
+}
+
+impl PalettedStorage {
+    /// Creates storage for `len` blocks, all set to `block`.
+    pub fn new(len: usize, block: BlockId) -> Self {
+        Self {
+            len,
+            palette: vec![block],
+            bits: 0,
+            data: Vec::new(),
+        }
+    }
+
+    /// Rebuilds storage from its raw parts, as returned by
+    /// [`PalettedStorage::palette`], [`PalettedStorage::bits`] and
+    /// [`PalettedStorage::data`].
+    ///
+    /// Returns `None` if the parts are inconsistent.
+    pub fn from_raw_parts(
+        len: usize,
+        palette: Vec<BlockId>,
+        bits: u32,
+        data: Vec<u64>,
+    ) -> Option<Self> {
+        if palette.is_empty() || bits > 16 || bits < bits_for(palette.len()) {
+            return None;
+        }
+        if data.len() != words_for(len, bits) {
+            return None;
+        }
+        let storage = Self {
+            len,
+            palette,
+            bits,
+            data,
+        };
+        if (0..len).any(|i| storage.palette_index(i) >= storage.palette.len()) {
+            return None;
+        }
+        Some(storage)
+    }
+
+    /// Number of blocks stored.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The distinct blocks that can be referenced by the stored indices.
+    pub fn palette(&self) -> &[BlockId] {
+        &self.palette
+    }
+
+    /// Bits used by each stored index.
+    pub fn bits(&self) -> u32 {
+        self.bits
+    }
+
+    /// The packed palette indices.
+    pub fn data(&self) -> &[u64] {
+        &self.data
+    }
+
+    /// Returns the block every position is set to, if they are all the same.
+    ///
+    /// Only storage that was created uniform, filled or compacted is detected.
+    pub fn single(&self) -> Option<BlockId> {
+        (self.bits == 0).then_some(self.palette[0])
+    }
+
+    /// Returns the block at the given index.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the index is out of bounds.
+    pub fn get(&self, index: usize) -> BlockId {
+        assert!(index < self.len, "index out of bounds");
+        self.palette[self.palette_index(index)]
+    }
+
+    /// Sets the block at the given index, returning the previous one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the index is out of bounds.
+    pub fn set(&mut self, index: usize, block: BlockId) -> BlockId {
+        assert!(index < self.len, "index out of bounds");
+        let previous = self.get(index);
+        if previous == block {
+            return previous;
+        }
+
+        let palette_index = match self.palette.iter().position(|b| *b == block) {
+            Some(palette_index) => palette_index,
+            None => {
+                self.palette.push(block);
+                let bits = bits_for(self.palette.len());
+                if bits > self.bits {
+                    self.resize(bits);
+                }
+                self.palette.len() - 1
+            }
+        };
+        self.set_palette_index(index, palette_index);
+        previous
+    }
+
+    /// Sets every block to `block`, releasing the packed indices.
+    pub fn fill(&mut self, block: BlockId) {
+        *self = Self::new(self.len, block);
+    }
+
+    /// Drops unused palette entries and packs the indices with as few bits as
+    /// possible.
+    pub fn compact(&mut self) {
+        let mut remap = vec![None; self.palette.len()];
+        let mut palette = Vec::new();
+        for i in 0..self.len {
+            let old = self.palette_index(i);
+            if remap[old].is_none() {
+                remap[old] = Some(palette.len());
+                palette.push(self.palette[old]);
+            }
+        }
+        if palette.len() == self.palette.len() && bits_for(palette.len()) == self.bits {
+            return;
+        }
+        if palette.len() <= 1 {
+            let block = palette.first().copied().unwrap_or(self.palette[0]);
+            self.fill(block);
+            return;
+        }
+
+        let mut compacted = Self {
+            len: self.len,
+            bits: bits_for(palette.len()),
+            palette,
+            data: Vec::new(),
+        };
+        compacted.data = vec![0; words_for(self.len, compacted.bits)];
+        for i in 0..self.len {
+            let new = remap[self.palette_index(i)].unwrap();
+            compacted.set_palette_index(i, new);
+        }
+        *self = compacted;
+    }
+
+    fn palette_index(&self, index: usize) -> usize {
+        if self.bits == 0 {
+            return 0;
+        }
+        let per_word = 64 / self.bits as usize;
+        let shift = (index % per_word) as u32 * self.bits;
+        ((self.data[index / per_word] >> shift) & mask(self.bits)) as usize
+    }
+
+    fn set_palette_index(&mut self, index: usize, palette_index: usize) {
+        let per_word = 64 / self.bits as usize;
+        let shift = (index % per_word) as u32 * self.bits;
+        let word = &mut self.data[index / per_word];
+        *word = (*word & !(mask(self.bits) << shift)) | ((palette_index as u64) << shift);
+    }
+
+    /// Repacks the indices using the given amount of bits.
+    fn resize(&mut self, bits: u32) {
+        let mut resized = Self {
+            len: self.len,
+            palette: Vec::new(),
+            bits,
+            data: vec![0; words_for(self.len, bits)],
+        };
+        for i in 0..self.len {
+            resized.set_palette_index(i, self.palette_index(i));
+        }
+        self.bits = bits;
+        self.data = resized.data;
+    }
+}
+
+/// Bits needed to index a palette with `len` entries.
+fn bits_for(len: usize) -> u32 {
+    if len <= 1 {
+        0
+    } else {
+        usize::BITS - (len - 1).leading_zeros()
+    }
+}
+
+/// Words needed to store `len` indices of `bits` bits each.
+pub(crate) fn words_for(len: usize, bits: u32) -> usize {
+    if bits == 0 {
+        0
+    } else {
+        len.div_ceil(64 / bits as usize)
+    }
+}
+
+fn mask(bits: u32) -> u64 {
+    (1 << bits) - 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(id: u16) -> BlockId {
+        BlockId::from_raw(id)
+    }
+
+    #[test]
+    fn grows_across_bit_widths() {
+        let mut storage = PalettedStorage::new(4096, BlockId::AIR);
+        assert_eq!(storage.bits(), 0);
+        assert!(storage.data().is_empty());
+
+        // 2 entries take 1 bit, 3-4 take 2, 5-8 take 3, and so on.
+        let expected = [1, 2, 2, 3, 3, 3, 3, 4, 4];
+        for (i, bits) in expected.into_iter().enumerate() {
+            let id = i as u16 + 1;
+            assert_eq!(storage.set(id as usize * 7, block(id)), BlockId::AIR);
+            assert_eq!(storage.bits(), bits);
+            assert_eq!(storage.palette().len(), i + 2);
+            assert_eq!(storage.data().len(), words_for(4096, bits));
+            for earlier in 1..=id {
+                assert_eq!(storage.get(earlier as usize * 7), block(earlier));
+            }
+        }
+        assert_eq!(storage.get(0), BlockId::AIR);
+        assert_eq!(storage.get(4095), BlockId::AIR);
+        assert_eq!(storage.single(), None);
+    }
+
+    #[test]
+    fn set_returns_the_previous_block() {
+        let mut storage = PalettedStorage::new(64, BlockId::AIR);
+        assert_eq!(storage.set(3, block(1)), BlockId::AIR);
+        assert_eq!(storage.set(3, block(2)), block(1));
+        assert_eq!(storage.set(3, block(2)), block(2));
+        assert_eq!(storage.get(3), block(2));
+    }
+
+    #[test]
+    fn compact_drops_unused_entries() {
+        let mut storage = PalettedStorage::new(4096, BlockId::AIR);
+        for id in 1..=4 {
+            storage.set(id, block(id as u16));
+        }
+        assert_eq!(storage.bits(), 3);
+        storage.set(1, BlockId::AIR);
+        storage.set(2, BlockId::AIR);
+
+        storage.compact();
+        assert_eq!(storage.palette(), &[BlockId::AIR, block(3), block(4)]);
+        assert_eq!(storage.bits(), 2);
+        assert_eq!(storage.get(2), BlockId::AIR);
+        assert_eq!(storage.get(3), block(3));
+        assert_eq!(storage.get(4), block(4));
+    }
+
+    #[test]
+    fn compact_returns_to_a_single_block() {
+        let mut storage = PalettedStorage::new(4096, BlockId::AIR);
+        storage.set(10, block(1));
+        storage.set(20, block(2));
+        storage.set(10, BlockId::AIR);
+        storage.set(20, BlockId::AIR);
+        assert_eq!(storage.single(), None);
+
+        storage.compact();
+        assert_eq!(storage.single(), Some(BlockId::AIR));
+        assert_eq!(storage.bits(), 0);
+        assert!(storage.data().is_empty());
+        assert_eq!(storage.palette(), &[BlockId::AIR]);
+
+        // Storage overwritten with another block keeps that one.
+        let mut storage = PalettedStorage::new(2, BlockId::AIR);
+        storage.set(0, block(5));
+        storage.set(1, block(5));
+        storage.compact();
+        assert_eq!(storage.single(), Some(block(5)));
+    }
+
+    #[test]
+    fn raw_parts_round_trip() {
+        let mut storage = PalettedStorage::new(100, BlockId::AIR);
+        for i in 0..100 {
+            storage.set(i, block((i % 5) as u16));
+        }
+        let rebuilt = PalettedStorage::from_raw_parts(
+            storage.len(),
+            storage.palette().to_vec(),
+            storage.bits(),
+            storage.data().to_vec(),
+        )
+        .unwrap();
+        assert!((0..100).all(|i| rebuilt.get(i) == storage.get(i)));
+    }
+
+    #[test]
+    fn from_raw_parts_rejects_inconsistent_parts() {
+        let palette = vec![BlockId::AIR, block(1), block(2)];
+        let words = words_for(64, 2);
+        assert!(PalettedStorage::from_raw_parts(64, palette.clone(), 2, vec![0; words]).is_some());
+
+        // Empty palette.
+        assert!(PalettedStorage::from_raw_parts(64, Vec::new(), 0, Vec::new()).is_none());
+        // Too few bits for the palette.
+        let one_bit = vec![0; words_for(64, 1)];
+        assert!(PalettedStorage::from_raw_parts(64, palette.clone(), 1, one_bit).is_none());
+        // Too many bits.
+        assert!(PalettedStorage::from_raw_parts(64, palette.clone(), 17, vec![0; 64]).is_none());
+        // Wrong amount of data.
+        assert!(
+            PalettedStorage::from_raw_parts(64, palette.clone(), 2, vec![0; words + 1]).is_none()
+        );
+        // An index past the end of the palette.
+        let mut data = vec![0; words];
+        data[0] = 0b11 << 10;
+        assert!(PalettedStorage::from_raw_parts(64, palette, 2, data).is_none());
+    }
+}
@@ -0,0 +1,593 @@
+//! Messages exchanged between clients and servers.
+//!
+//! Messages are sent in frames: a `u32` with the length of the payload,
+//! followed by the payload itself. The payload starts with a `u8` tag
+//! identifying the message, followed by its fields. Like in
+//! [crate::codec], numbers are little-endian; strings are a `u16` length
+//! followed by UTF-8 bytes, and chunks are encoded with [crate::codec].
+//!
+//! The first message sent by a client is always a
+//! [`ClientMessage::Handshake`] carrying its [PROTOCOL_VERSION]. Anything
+//! else about the protocol can change between versions.
+
+use std::{
+    io::{Read, Write},
+    sync::Arc,
+};
+
+use crate::{
+    block::BlockId,
+    chunk::Chunk,
+    codec::{self, ChunkCodecError, Compression, Reader, UnexpectedEof},
+    math::{Vec2, Vec3},
+};
+
+/// Version of the protocol implemented by this build.
+///
+/// Must be bumped on any change to the messages or their encoding.
+pub const PROTOCOL_VERSION: u32 = 4;
+/// Largest payload accepted in a frame.
+pub const MAX_FRAME_LEN: usize = 1 << 20;
+/// Length of the prefix of every frame.
+pub const FRAME_HEADER_LEN: usize = 4;
+
+#[derive(Debug)]
+pub enum ProtocolError {
+    Io(std::io::ErrorKind),
+    /// A frame announced a payload larger than [MAX_FRAME_LEN].
+    FrameTooLarge(usize),
+    /// The payload ended before the message did.
+    UnexpectedEof,
+    /// There is data left after the message.
+    TrailingData,
+    UnknownMessage(u8),
+    InvalidString,
+    Chunk(ChunkCodecError),
+    /// The other side closed the connection.
+    ConnectionClosed,
+    /// Too many messages are waiting to be written, the other side is not
+    /// reading them fast enough.
+    QueueFull,
+}
+
+impl From<std::io::Error> for ProtocolError {
+    fn from(value: std::io::Error) -> Self {
+        ProtocolError::Io(value.kind())
+    }
+}
+
+impl From<UnexpectedEof> for ProtocolError {
+    fn from(_: UnexpectedEof) -> Self {
+        ProtocolError::UnexpectedEof
+    }
+}
+
+impl From<ChunkCodecError> for ProtocolError {
+    fn from(value: ChunkCodecError) -> Self {
+        ProtocolError::Chunk(value)
+    }
+}
+
+/// Messages sent by clients to the server.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ClientMessage {
+    /// Asks to join the server. Always the first message of a connection.
+    Handshake {
+        protocol_version: u32,
+        name: String,
+    },
+    /// The player moved.
+    PlayerPosition {
+        pos: Vec3<f32>,
+    },
+    /// The player broke or placed a block.
+    SetBlock {
+        pos: Vec3<i32>,
+        block: BlockId,
+    },
+    Chat {
+        message: String,
+    },
+    /// The client is leaving.
+    Disconnect {
+        reason: String,
+    },
+    /// Answers a [`ServerMessage::KeepAlive`].
+    KeepAlive {
+        id: u64,
+    },
+}
+
+/// Messages sent by the server to its clients.
+#[derive(Clone, Debug)]
+pub enum ServerMessage {
+    /// The client joined the server.
+    HandshakeAccepted {
+        seed: u32,
+        /// Names of the blocks in id order, see
+        /// [`BlockRegistry::remap`](crate::block::BlockRegistry::remap).
+        blocks: Vec<String>,
+    },
+    /// A chunk entered the view of the client.
+    ChunkData {
+        pos: Vec2<i32>,
+        chunk: Arc<Chunk>,
+    },
+    /// A block changed in a chunk the client knows about.
+    BlockChange {
+        pos: Vec3<i32>,
+        block: BlockId,
+    },
+    /// The player was moved by the server.
+    PlayerPosition {
+        pos: Vec3<f32>,
+    },
+    Chat {
+        message: String,
+    },
+    /// The server is closing the connection.
+    Disconnect {
+        reason: String,
+    },
+    /// Checks that the client is still there. Must be answered with a
+    /// [`ClientMessage::KeepAlive`] with the same id.
+    KeepAlive {
+        id: u64,
+    },
+    /// A chunk left the view of the client, which will not receive updates
+    /// for it anymore.
+    UnloadChunk {
+        pos: Vec2<i32>,
+    },
+}
+
+/// A message that can be sent over the network.
+pub trait Message: Sized {
+    /// Appends the payload of the message to `buf`.
+    fn encode(&self, buf: &mut Vec<u8>);
+
+    /// Decodes a whole payload.
+    fn decode(payload: &[u8]) -> Result<Self, ProtocolError>;
+}
+
+impl Message for ClientMessage {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            Self::Handshake {
+                protocol_version,
+                name,
+            } => {
+                buf.push(0);
+                buf.extend_from_slice(&protocol_version.to_le_bytes());
+                write_string(buf, name);
+            }
+            Self::PlayerPosition { pos } => {
+                buf.push(1);
+                write_vec3_f32(buf, *pos);
+            }
+            Self::SetBlock { pos, block } => {
+                buf.push(2);
+                write_vec3_i32(buf, *pos);
+                buf.extend_from_slice(&block.raw().to_le_bytes());
+            }
+            Self::Chat { message } => {
+                buf.push(3);
+                write_string(buf, message);
+            }
+            Self::Disconnect { reason } => {
+                buf.push(4);
+                write_string(buf, reason);
+            }
+            Self::KeepAlive { id } => {
+                buf.push(5);
+                buf.extend_from_slice(&id.to_le_bytes());
+            }
+        }
+    }
+
+    fn decode(payload: &[u8]) -> Result<Self, ProtocolError> {
+        let mut reader = Reader::new(payload);
+        let message = match reader.u8()? {
+            0 => Self::Handshake {
+                protocol_version: reader.u32()?,
+                name: read_string(&mut reader)?,
+            },
+            1 => Self::PlayerPosition {
+                pos: read_vec3_f32(&mut reader)?,
+            },
+            2 => Self::SetBlock {
+                pos: read_vec3_i32(&mut reader)?,
+                block: BlockId::from_raw(reader.u16()?),
+            },
+            3 => Self::Chat {
+                message: read_string(&mut reader)?,
+            },
+            4 => Self::Disconnect {
+                reason: read_string(&mut reader)?,
+            },
+            5 => Self::KeepAlive { id: reader.u64()? },
+            tag => return Err(ProtocolError::UnknownMessage(tag)),
+        };
+        finish(reader, message)
+    }
+}
+
+impl Message for ServerMessage {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            Self::HandshakeAccepted { seed, blocks } => {
+                buf.push(0);
+                buf.extend_from_slice(&seed.to_le_bytes());
+                buf.extend_from_slice(&(blocks.len() as u16).to_le_bytes());
+                for name in blocks {
+                    write_string(buf, name);
+                }
+            }
+            Self::ChunkData { pos, chunk } => {
+                buf.push(1);
+                let bytes = codec::encode_chunk(*pos, chunk, Compression::Lz4);
+                buf.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+                buf.extend_from_slice(&bytes);
+            }
+            Self::BlockChange { pos, block } => {
+                buf.push(2);
+                write_vec3_i32(buf, *pos);
+                buf.extend_from_slice(&block.raw().to_le_bytes());
+            }
+            Self::PlayerPosition { pos } => {
+                buf.push(3);
+                write_vec3_f32(buf, *pos);
+            }
+            Self::Chat { message } => {
+                buf.push(4);
+                write_string(buf, message);
+            }
+            Self::Disconnect { reason } => {
+                buf.push(5);
+                write_string(buf, reason);
+            }
+            Self::KeepAlive { id } => {
+                buf.push(6);
+                buf.extend_from_slice(&id.to_le_bytes());
+            }
+            Self::UnloadChunk { pos } => {
+                buf.push(7);
+                buf.extend_from_slice(&pos.x.to_le_bytes());
+                buf.extend_from_slice(&pos.y.to_le_bytes());
+            }
+        }
+    }
+
+    fn decode(payload: &[u8]) -> Result<Self, ProtocolError> {
+        let mut reader = Reader::new(payload);
+        let message = match reader.u8()? {
+            0 => Self::HandshakeAccepted {
+                seed: reader.u32()?,
+                blocks: (0..reader.u16()?)
+                    .map(|_| read_string(&mut reader))
+                    .collect::<Result<_, _>>()?,
+            },
+            1 => {
+                let len = reader.u32()? as usize;
+                let (pos, chunk) = codec::decode_chunk(reader.take(len)?)?;
+                Self::ChunkData {
+                    pos,
+                    chunk: Arc::new(chunk),
+                }
+            }
+            2 => Self::BlockChange {
+                pos: read_vec3_i32(&mut reader)?,
+                block: BlockId::from_raw(reader.u16()?),
+            },
+            3 => Self::PlayerPosition {
+                pos: read_vec3_f32(&mut reader)?,
+            },
+            4 => Self::Chat {
+                message: read_string(&mut reader)?,
+            },
+            5 => Self::Disconnect {
+                reason: read_string(&mut reader)?,
+            },
+            6 => Self::KeepAlive { id: reader.u64()? },
+            7 => Self::UnloadChunk {
+                pos: Vec2::new(reader.i32()?, reader.i32()?),
+            },
+            tag => return Err(ProtocolError::UnknownMessage(tag)),
+        };
+        finish(reader, message)
+    }
+}
+
+/// Appends a frame holding the given message to `buf`.
+pub fn encode_frame(buf: &mut Vec<u8>, message: &impl Message) {
+    let start = buf.len();
+    buf.extend_from_slice(&[0; FRAME_HEADER_LEN]);
+    message.encode(buf);
+    let len = (buf.len() - start - FRAME_HEADER_LEN) as u32;
+    buf[start..start + FRAME_HEADER_LEN].copy_from_slice(&len.to_le_bytes());
+}
+
+/// Writes a single frame holding the given message.
+pub fn write_message(writer: &mut impl Write, message: &impl Message) -> Result<(), ProtocolError> {
+    let mut buf = Vec::new();
+    encode_frame(&mut buf, message);
+    writer.write_all(&buf)?;
+    Ok(())
+}
+
+/// Reads a single frame, blocking until the whole message arrived.
+pub fn read_message<M: Message>(reader: &mut impl Read) -> Result<M, ProtocolError> {
+    let mut header = [0; FRAME_HEADER_LEN];
+    reader.read_exact(&mut header)?;
+    let len = frame_len(header)?;
+    let mut payload = vec![0; len];
+    reader.read_exact(&mut payload)?;
+    M::decode(&payload)
+}
+
+/// Splits a stream of bytes received in arbitrary pieces into messages.
+#[derive(Default)]
+pub struct FrameDecoder {
+    buf: Vec<u8>,
+}
+
+impl FrameDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds received bytes to the stream.
+    pub fn extend(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    /// Returns the next message of the stream, or `None` if it has not been
+    /// completely received yet.
+    pub fn next_message<M: Message>(&mut self) -> Result<Option<M>, ProtocolError> {
+        let Some(header) = self.buf.first_chunk::<FRAME_HEADER_LEN>() else {
+            return Ok(None);
+        };
+        let len = frame_len(*header)?;
+        if self.buf.len() < FRAME_HEADER_LEN + len {
+            return Ok(None);
+        }
+        let message = M::decode(&self.buf[FRAME_HEADER_LEN..FRAME_HEADER_LEN + len]);
+        self.buf.drain(..FRAME_HEADER_LEN + len);
+        message.map(Some)
+    }
+}
+
+fn frame_len(header: [u8; FRAME_HEADER_LEN]) -> Result<usize, ProtocolError> {
+    let len = u32::from_le_bytes(header) as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(ProtocolError::FrameTooLarge(len));
+    }
+    Ok(len)
+}
+
+fn finish<M>(reader: Reader, message: M) -> Result<M, ProtocolError> {
+    if reader.remaining() > 0 {
+        return Err(ProtocolError::TrailingData);
+    }
+    Ok(message)
+}
+
+fn write_string(buf: &mut Vec<u8>, string: &str) {
+    // Longer strings are cut, making sure not to split a character.
+    let mut len = string.len().min(u16::MAX as usize);
+    while !string.is_char_boundary(len) {
+        len -= 1;
+    }
+    buf.extend_from_slice(&(len as u16).to_le_bytes());
+    buf.extend_from_slice(&string.as_bytes()[..len]);
+}
+
+fn read_string(reader: &mut Reader) -> Result<String, ProtocolError> {
+    let len = reader.u16()? as usize;
+    let bytes = reader.take(len)?;
+    String::from_utf8(bytes.to_vec()).map_err(|_| ProtocolError::InvalidString)
+}
+
+fn write_vec3_f32(buf: &mut Vec<u8>, v: Vec3<f32>) {
+    for x in v {
+        buf.extend_from_slice(&x.to_le_bytes());
+    }
+}
+
+fn read_vec3_f32(reader: &mut Reader) -> Result<Vec3<f32>, ProtocolError> {
+    Ok(Vec3::new(reader.f32()?, reader.f32()?, reader.f32()?))
+}
+
+fn write_vec3_i32(buf: &mut Vec<u8>, v: Vec3<i32>) {
+    for x in v {
+        buf.extend_from_slice(&x.to_le_bytes());
+    }
+}
+
+fn read_vec3_i32(reader: &mut Reader) -> Result<Vec3<i32>, ProtocolError> {
+    Ok(Vec3::new(reader.i32()?, reader.i32()?, reader.i32()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{TcpListener, TcpStream};
+
+    use super::*;
+
+    fn client_messages() -> Vec<ClientMessage> {
+        vec![
+            ClientMessage::Handshake {
+                protocol_version: PROTOCOL_VERSION,
+                name: "Ünïcode name".to_owned(),
+            },
+            ClientMessage::PlayerPosition {
+                pos: Vec3::new(-1.5, 80.25, 1e6),
+            },
+            ClientMessage::SetBlock {
+                pos: Vec3::new(-17, 255, i32::MAX),
+                block: BlockId::from_raw(3),
+            },
+            ClientMessage::Chat {
+                message: String::new(),
+            },
+            ClientMessage::Disconnect {
+                reason: "bye".to_owned(),
+            },
+            ClientMessage::KeepAlive { id: u64::MAX },
+        ]
+    }
+
+    fn server_messages() -> Vec<ServerMessage> {
+        let mut chunk = Chunk::empty();
+        chunk.set(Vec3::new(3, 70, 9), BlockId::from_raw(2));
+        vec![
+            ServerMessage::HandshakeAccepted {
+                seed: 42,
+                blocks: vec!["air".to_owned(), "stone".to_owned()],
+            },
+            ServerMessage::ChunkData {
+                pos: Vec2::new(-3, 7),
+                chunk: Arc::new(chunk),
+            },
+            ServerMessage::BlockChange {
+                pos: Vec3::new(1, 2, -3),
+                block: BlockId::AIR,
+            },
+            ServerMessage::PlayerPosition {
+                pos: Vec3::new(0.0, 100.0, -0.5),
+            },
+            ServerMessage::Chat {
+                message: "<a> hi".to_owned(),
+            },
+            ServerMessage::Disconnect {
+                reason: "Server closed".to_owned(),
+            },
+            ServerMessage::KeepAlive { id: 7 },
+            ServerMessage::UnloadChunk {
+                pos: Vec2::new(i32::MIN, 0),
+            },
+        ]
+    }
+
+    /// Server messages hold chunks, which cannot be compared, so messages
+    /// are compared through their encoding.
+    fn payload(message: &impl Message) -> Vec<u8> {
+        let mut buf = Vec::new();
+        message.encode(&mut buf);
+        buf
+    }
+
+    /// Feeds the frames to a decoder in pieces of `piece` bytes.
+    fn decode_all<M: Message>(frames: &[u8], piece: usize) -> Vec<M> {
+        let mut decoder = FrameDecoder::new();
+        let mut messages = Vec::new();
+        for bytes in frames.chunks(piece) {
+            decoder.extend(bytes);
+            while let Some(message) = decoder.next_message().unwrap() {
+                messages.push(message);
+            }
+        }
+        messages
+    }
+
+    #[test]
+    fn client_messages_round_trip() {
+        let messages = client_messages();
+        let mut frames = Vec::new();
+        for message in &messages {
+            encode_frame(&mut frames, message);
+        }
+        for piece in [1, 7, frames.len()] {
+            assert_eq!(decode_all::<ClientMessage>(&frames, piece), messages);
+        }
+    }
+
+    #[test]
+    fn server_messages_round_trip() {
+        let messages = server_messages();
+        let mut frames = Vec::new();
+        for message in &messages {
+            encode_frame(&mut frames, message);
+        }
+        for piece in [1, 7, frames.len()] {
+            let decoded = decode_all::<ServerMessage>(&frames, piece);
+            assert_eq!(decoded.len(), messages.len());
+            for (decoded, message) in decoded.iter().zip(&messages) {
+                assert_eq!(payload(decoded), payload(message));
+            }
+        }
+    }
+
+    #[test]
+    fn messages_go_through_tcp_streams() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+
+        for message in client_messages() {
+            write_message(&mut client, &message).unwrap();
+            assert_eq!(read_message::<ClientMessage>(&mut server).unwrap(), message);
+        }
+        for message in server_messages() {
+            write_message(&mut server, &message).unwrap();
+            let received = read_message::<ServerMessage>(&mut client).unwrap();
+            assert_eq!(payload(&received), payload(&message));
+        }
+
+        drop(server);
+        assert!(matches!(
+            read_message::<ServerMessage>(&mut client),
+            Err(ProtocolError::Io(std::io::ErrorKind::UnexpectedEof))
+        ));
+    }
+
+    #[test]
+    fn rejects_large_frames() {
+        let mut decoder = FrameDecoder::new();
+        decoder.extend(&(MAX_FRAME_LEN as u32 + 1).to_le_bytes());
+        assert!(matches!(
+            decoder.next_message::<ClientMessage>(),
+            Err(ProtocolError::FrameTooLarge(len)) if len == MAX_FRAME_LEN + 1
+        ));
+
+        let mut header = &(MAX_FRAME_LEN as u32 + 1).to_le_bytes()[..];
+        assert!(matches!(
+            read_message::<ClientMessage>(&mut header),
+            Err(ProtocolError::FrameTooLarge(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_unknown_messages() {
+        assert!(matches!(
+            ClientMessage::decode(&[6]),
+            Err(ProtocolError::UnknownMessage(6))
+        ));
+        assert!(matches!(
+            ServerMessage::decode(&[8]),
+            Err(ProtocolError::UnknownMessage(8))
+        ));
+    }
+
+    #[test]
+    fn rejects_invalid_payloads() {
+        let mut trailing = payload(&ClientMessage::KeepAlive { id: 1 });
+        trailing.push(0);
+        assert!(matches!(
+            ClientMessage::decode(&trailing),
+            Err(ProtocolError::TrailingData)
+        ));
+
+        let truncated = payload(&ServerMessage::KeepAlive { id: 1 });
+        assert!(matches!(
+            ServerMessage::decode(&truncated[..truncated.len() - 1]),
+            Err(ProtocolError::UnexpectedEof)
+        ));
+
+        // A chat message holding 2 bytes that are not UTF-8.
+        assert!(matches!(
+            ClientMessage::decode(&[3, 2, 0, 0xff, 0xfe]),
+            Err(ProtocolError::InvalidString)
+        ));
+    }
+}
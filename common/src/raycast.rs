@@ -0,0 +1,183 @@
+//! Finding the blocks along a ray.
+
+use crate::math::Vec3;
+
+/// The first block a ray hit.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RaycastHit {
+    /// World position of the block.
+    pub pos: Vec3<i32>,
+    /// Normal of the face the ray entered the block through, or zero if the
+    /// ray started inside the block.
+    pub normal: Vec3<i32>,
+    /// Distance travelled along the ray before entering the block.
+    pub distance: f32,
+}
+
+/// Walks the blocks crossed by a ray, in order, until `is_hit` accepts one
+/// or `max_distance` is reached.
+///
+/// Uses the voxel traversal algorithm by Amanatides and Woo, so every block
+/// the ray touches is visited exactly once. `dir` does not need to be
+/// normalized, but must not be zero.
+pub fn raycast(
+    origin: Vec3<f32>,
+    dir: Vec3<f32>,
+    max_distance: f32,
+    mut is_hit: impl FnMut(Vec3<i32>) -> bool,
+) -> Option<RaycastHit> {
+    let dir = dir.try_normalized()?;
+    let mut pos = origin.map(|x| x.floor() as i32);
+    let step = dir.map(|x| x.signum() as i32);
+    // Distance along the ray to cross a whole block on each axis.
+    let delta = dir.map(|x| (1.0 / x).abs());
+    // Distance along the ray to the next block boundary on each axis.
+    let mut next = Vec3::<f32>::zero();
+    for axis in 0..3 {
+        next[axis] = if dir[axis] > 0.0 {
+            (pos[axis] as f32 + 1.0 - origin[axis]) * delta[axis]
+        } else if dir[axis] < 0.0 {
+            (origin[axis] - pos[axis] as f32) * delta[axis]
+        } else {
+            f32::INFINITY
+        };
+    }
+
+    let mut normal = Vec3::zero();
+    let mut distance = 0.0;
+    while distance <= max_distance {
+        if is_hit(pos) {
+            return Some(RaycastHit {
+                pos,
+                normal,
+                distance,
+            });
+        }
+        let axis = if next.x < next.y {
+            if next.x < next.z {
+                0
+            } else {
+                2
+            }
+        } else if next.y < next.z {
+            1
+        } else {
+            2
+        };
+        distance = next[axis];
+        next[axis] += delta[axis];
+        pos[axis] += step[axis];
+        normal = Vec3::zero();
+        normal[axis] = -step[axis];
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{block::BlockId, chunk::Chunk, math::Vec2, world::World};
+
+    const STONE: BlockId = BlockId::from_raw(1);
+
+    /// A world made of the chunks around the origin, holding the given
+    /// blocks.
+    fn world(blocks: &[Vec3<i32>]) -> World {
+        let mut world = World::new();
+        for x in -2..2 {
+            for z in -2..2 {
+                world.insert_chunk(Vec2::new(x, z), Chunk::empty());
+            }
+        }
+        for pos in blocks {
+            world.set_block(*pos, STONE).unwrap();
+        }
+        world
+    }
+
+    fn cast(world: &World, origin: Vec3<f32>, dir: Vec3<f32>, max: f32) -> Option<RaycastHit> {
+        raycast(origin, dir, max, |pos| {
+            world.get_block(pos).is_some_and(|block| !block.is_air())
+        })
+    }
+
+    #[test]
+    fn hits_along_each_axis() {
+        let world = world(&[
+            Vec3::new(5, 10, 0),
+            Vec3::new(-3, 10, 0),
+            Vec3::new(0, 14, 0),
+            Vec3::new(0, 6, 0),
+            Vec3::new(0, 10, 5),
+            Vec3::new(0, 10, -3),
+        ]);
+        let origin = Vec3::new(0.5, 10.5, 0.5);
+        let cases = [
+            (Vec3::unit_x(), Vec3::new(5, 10, 0), 4.5),
+            (-Vec3::unit_x(), Vec3::new(-3, 10, 0), 2.5),
+            (Vec3::unit_y(), Vec3::new(0, 14, 0), 3.5),
+            (-Vec3::unit_y(), Vec3::new(0, 6, 0), 3.5),
+            (Vec3::unit_z(), Vec3::new(0, 10, 5), 4.5),
+            (-Vec3::unit_z(), Vec3::new(0, 10, -3), 2.5),
+        ];
+        for (dir, pos, distance) in cases {
+            let hit = cast(&world, origin, dir, 10.0).unwrap();
+            assert_eq!(hit.pos, pos);
+            assert_eq!(hit.normal, -dir.map(|x| x as i32));
+            assert!((hit.distance - distance).abs() < 1e-5, "{:?}", hit);
+        }
+    }
+
+    #[test]
+    fn crosses_chunk_boundaries_at_negative_coordinates() {
+        // A wall at x = -17, the first column of the chunks at x = -2.
+        let wall = (-32..0).map(|z| Vec3::new(-17, 5, z)).collect::<Vec<_>>();
+        let world = world(&wall);
+        let origin = Vec3::new(-10.5, 5.5, -10.5);
+        let dir = Vec3::new(-2.0, 0.0, -1.0);
+
+        let mut visited = Vec::<Vec3<i32>>::new();
+        let hit = raycast(origin, dir, 10.0, |pos| {
+            visited.push(pos);
+            world.get_block(pos).is_some_and(|block| !block.is_air())
+        })
+        .unwrap();
+        assert_eq!(hit.pos, Vec3::new(-17, 5, -14));
+        assert_eq!(World::chunk_pos(hit.pos), Vec2::new(-2, -1));
+        assert_eq!(hit.normal, Vec3::new(1, 0, 0));
+        let expected = Vec3::new(5.5f32, 0.0, 2.75).magnitude();
+        assert!((hit.distance - expected).abs() < 1e-4, "{:?}", hit);
+
+        // Every step moves to a block sharing a face with the previous one.
+        for pair in visited.windows(2) {
+            let diff = pair[1] - pair[0];
+            assert_eq!(diff.map(i32::abs).sum(), 1, "{:?}", pair);
+        }
+    }
+
+    #[test]
+    fn starts_inside_a_block() {
+        let world = world(&[Vec3::new(-1, 20, -1)]);
+        let hit = cast(&world, Vec3::new(-0.5, 20.5, -0.5), Vec3::unit_x(), 10.0).unwrap();
+        assert_eq!(hit.pos, Vec3::new(-1, 20, -1));
+        assert_eq!(hit.normal, Vec3::zero());
+        assert_eq!(hit.distance, 0.0);
+    }
+
+    #[test]
+    fn misses_beyond_max_distance() {
+        let world = world(&[Vec3::new(5, 10, 0)]);
+        let origin = Vec3::new(0.5, 10.5, 0.5);
+        assert_eq!(cast(&world, origin, Vec3::unit_x(), 4.0), None);
+        assert!(cast(&world, origin, Vec3::unit_x(), 5.0).is_some());
+        assert_eq!(cast(&world, origin, -Vec3::unit_x(), 30.0), None);
+    }
+
+    #[test]
+    fn zero_direction_never_hits() {
+        let hit = raycast(Vec3::zero(), Vec3::zero(), 10.0, |_| {
+            panic!("no block should be visited")
+        });
+        assert_eq!(hit, None);
+    }
+}
@@ -0,0 +1,408 @@
+//! Region files, holding the saved chunks of a 32x32 area of chunk columns.
+//!
+//! A region file starts with a header made of [REGION_MAGIC], a format version
+//! and an offset table with one `(offset, length)` pair of `u32`s per column,
+//! ordered by `z * 32 + x`. A length of 0 means the column was never saved.
+//! Chunks are stored after the header, encoded with [crate::codec].
+//!
+//! Chunks are never overwritten in place: a new copy is appended to the file
+//! and flushed before its table entry is updated, so a crash leaves either the
+//! old or the new chunk. Space used by outdated copies is reclaimed by
+//! rewriting the whole file once it has grown enough.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    chunk::Chunk,
+    codec::{self, Compression},
+    math::Vec2,
+    save::{self, SaveError},
+};
+
+/// Identifies region files.
+pub const REGION_MAGIC: [u8; 4] = *b"EXRG";
+/// Version of the region file layout.
+pub const REGION_VERSION: u16 = 1;
+/// Width and depth of a region, in chunk columns.
+pub const REGION_SIZE: i32 = 32;
+
+const COLUMNS: usize = (REGION_SIZE * REGION_SIZE) as usize;
+const ENTRY_LEN: usize = 8;
+const HEADER_LEN: usize = 8 + COLUMNS * ENTRY_LEN;
+/// Files are not compacted until they waste at least this many bytes.
+const MIN_COMPACT_WASTE: u64 = 1 << 20;
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Entry {
+    offset: u32,
+    len: u32,
+}
+
+impl Entry {
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+/// An open region file.
+pub struct RegionFile {
+    path: PathBuf,
+    file: File,
+    entries: Vec<Entry>,
+    /// Current size of the file.
+    len: u64,
+}
+
+impl RegionFile {
+    /// Returns the position of the region containing the given chunk column.
+    pub fn region_pos(chunk_pos: Vec2<i32>) -> Vec2<i32> {
+        chunk_pos.map(|x| x.div_euclid(REGION_SIZE))
+    }
+
+    /// Opens the region file at `path`, creating an empty one if it does not
+    /// exist.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, SaveError> {
+        let path = path.as_ref().to_path_buf();
+        if !path.exists() {
+            save::write_atomic(&path, |file| {
+                file.write_all(&header(&[Entry::default(); COLUMNS]))
+            })?;
+        }
+
+        let mut file = OpenOptions::new().read(true).write(true).open(&path)?;
+        let len = file.metadata()?.len();
+        let mut header = vec![0; HEADER_LEN];
+        file.read_exact(&mut header)
+            .map_err(|_| SaveError::InvalidRegion)?;
+        if header[..4] != REGION_MAGIC {
+            return Err(SaveError::InvalidRegion);
+        }
+        let version = u16::from_le_bytes([header[4], header[5]]);
+        if version != REGION_VERSION {
+            return Err(SaveError::UnsupportedVersion(version.into()));
+        }
+
+        let entries = header[8..]
+            .chunks_exact(ENTRY_LEN)
+            .map(|entry| Entry {
+                offset: u32::from_le_bytes(entry[..4].try_into().unwrap()),
+                len: u32::from_le_bytes(entry[4..].try_into().unwrap()),
+            })
+            .collect::<Vec<_>>();
+        let in_bounds = |entry: &Entry| {
+            entry.is_empty()
+                || (entry.offset as usize >= HEADER_LEN
+                    && entry.offset as u64 + entry.len as u64 <= len)
+        };
+        if !entries.iter().all(in_bounds) {
+            return Err(SaveError::InvalidRegion);
+        }
+
+        Ok(Self {
+            path,
+            file,
+            entries,
+            len,
+        })
+    }
+
+    /// Whether a copy of the given column is stored in this region.
+    pub fn contains(&self, pos: Vec2<i32>) -> bool {
+        !self.entries[slot(pos)].is_empty()
+    }
+
+    /// Reads the chunk at the given column, or `None` if it was never saved.
+    pub fn read_chunk(&mut self, pos: Vec2<i32>) -> Result<Option<Chunk>, SaveError> {
+        let entry = self.entries[slot(pos)];
+        if entry.is_empty() {
+            return Ok(None);
+        }
+        let mut bytes = vec![0; entry.len as usize];
+        self.file.seek(SeekFrom::Start(entry.offset as u64))?;
+        self.file.read_exact(&mut bytes)?;
+        let (chunk_pos, chunk) = codec::decode_chunk(&bytes)?;
+        if chunk_pos != pos {
+            return Err(SaveError::InvalidRegion);
+        }
+        Ok(Some(chunk))
+    }
+
+    /// Stores the chunk at the given column, replacing any previous copy.
+    pub fn write_chunk(&mut self, pos: Vec2<i32>, chunk: &Chunk) -> Result<(), SaveError> {
+        let bytes = codec::encode_chunk(pos, chunk, Compression::Lz4);
+        let entry = Entry {
+            offset: u32::try_from(self.len).map_err(|_| SaveError::RegionFull)?,
+            len: bytes.len() as u32,
+        };
+        if entry.offset.checked_add(entry.len).is_none() {
+            return Err(SaveError::RegionFull);
+        }
+
+        self.file.seek(SeekFrom::Start(self.len))?;
+        self.file.write_all(&bytes)?;
+        self.file.sync_data()?;
+        self.len += bytes.len() as u64;
+
+        let slot = slot(pos);
+        self.file
+            .seek(SeekFrom::Start((8 + slot * ENTRY_LEN) as u64))?;
+        self.file.write_all(&entry_bytes(entry))?;
+        self.file.sync_data()?;
+        self.entries[slot] = entry;
+
+        // The chunk is saved at this point, a failed compaction is tried
+        // again on the next write.
+        if self.wasted() >= MIN_COMPACT_WASTE.max(self.used()) {
+            if let Err(e) = self.compact() {
+                tracing::warn!("Could not compact {}: {:?}", self.path.display(), e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Rewrites the file without the space taken by outdated chunks.
+    pub fn compact(&mut self) -> Result<(), SaveError> {
+        let mut entries = self.entries.clone();
+        let mut offset = HEADER_LEN as u32;
+        for entry in entries.iter_mut().filter(|entry| !entry.is_empty()) {
+            entry.offset = offset;
+            offset += entry.len;
+        }
+
+        let old = &mut self.file;
+        let old_entries = &self.entries;
+        save::write_atomic(&self.path, |file| {
+            file.write_all(&header(&entries))?;
+            let mut bytes = Vec::new();
+            for entry in old_entries.iter().filter(|entry| !entry.is_empty()) {
+                bytes.resize(entry.len as usize, 0);
+                old.seek(SeekFrom::Start(entry.offset as u64))?;
+                old.read_exact(&mut bytes)?;
+                file.write_all(&bytes)?;
+            }
+            Ok(())
+        })?;
+
+        self.file = OpenOptions::new().read(true).write(true).open(&self.path)?;
+        self.entries = entries;
+        self.len = offset as u64;
+        Ok(())
+    }
+
+    /// Bytes taken by the header and the current copy of every chunk.
+    fn used(&self) -> u64 {
+        HEADER_LEN as u64
+            + self
+                .entries
+                .iter()
+                .map(|entry| entry.len as u64)
+                .sum::<u64>()
+    }
+
+    /// Bytes taken by outdated copies of chunks.
+    fn wasted(&self) -> u64 {
+        self.len.saturating_sub(self.used())
+    }
+}
+
+/// Index of the given column in the offset table.
+fn slot(pos: Vec2<i32>) -> usize {
+    let local = pos.map(|x| x.rem_euclid(REGION_SIZE) as usize);
+    local.y * REGION_SIZE as usize + local.x
+}
+
+fn header(entries: &[Entry]) -> Vec<u8> {
+    let mut header = Vec::with_capacity(HEADER_LEN);
+    header.extend_from_slice(&REGION_MAGIC);
+    header.extend_from_slice(&REGION_VERSION.to_le_bytes());
+    header.extend_from_slice(&[0; 2]);
+    for entry in entries {
+        header.extend_from_slice(&entry_bytes(*entry));
+    }
+    header
+}
+
+fn entry_bytes(entry: Entry) -> [u8; ENTRY_LEN] {
+    let mut bytes = [0; ENTRY_LEN];
+    bytes[..4].copy_from_slice(&entry.offset.to_le_bytes());
+    bytes[4..].copy_from_slice(&entry.len.to_le_bytes());
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::{block::BlockId, math::Vec3};
+
+    /// A path in the temporary directory that no other test uses.
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "explora-region-{}-{}.region",
+            std::process::id(),
+            name
+        ));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn chunk(block: u16) -> Chunk {
+        let mut chunk = Chunk::empty();
+        for x in 0..16 {
+            chunk.set(Vec3::new(x, x * 10, 15 - x), BlockId::from_raw(block));
+        }
+        chunk
+    }
+
+    fn assert_same(a: &Chunk, b: &Chunk) {
+        let encode = |chunk| codec::encode_chunk(Vec2::zero(), chunk, Compression::None);
+        assert!(encode(a) == encode(b), "the chunks differ");
+    }
+
+    /// Overwrites the offset table entry of the given column.
+    fn set_entry(path: &Path, pos: Vec2<i32>, entry: Entry) {
+        let mut file = OpenOptions::new().write(true).open(path).unwrap();
+        file.seek(SeekFrom::Start((8 + slot(pos) * ENTRY_LEN) as u64))
+            .unwrap();
+        file.write_all(&entry_bytes(entry)).unwrap();
+    }
+
+    #[test]
+    fn round_trips_chunks() {
+        let path = temp_path("round-trip");
+        let pos = Vec2::new(33, -1);
+        let mut region = RegionFile::open(&path).unwrap();
+        assert!(!region.contains(pos));
+        assert!(region.read_chunk(pos).unwrap().is_none());
+
+        region.write_chunk(pos, &chunk(1)).unwrap();
+        assert!(region.contains(pos));
+        assert_same(&region.read_chunk(pos).unwrap().unwrap(), &chunk(1));
+        assert!(region.read_chunk(Vec2::new(32, -1)).unwrap().is_none());
+
+        drop(region);
+        let mut region = RegionFile::open(&path).unwrap();
+        assert_same(&region.read_chunk(pos).unwrap().unwrap(), &chunk(1));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn compacts_overwritten_chunks() {
+        let path = temp_path("compact");
+        let (a, b) = (Vec2::new(0, 0), Vec2::new(31, 31));
+        let mut region = RegionFile::open(&path).unwrap();
+        region.write_chunk(a, &chunk(1)).unwrap();
+        region.write_chunk(b, &chunk(2)).unwrap();
+        region.write_chunk(a, &chunk(3)).unwrap();
+        assert!(region.wasted() > 0);
+        assert_same(&region.read_chunk(a).unwrap().unwrap(), &chunk(3));
+
+        region.compact().unwrap();
+        assert_eq!(region.wasted(), 0);
+        assert_eq!(fs::metadata(&path).unwrap().len(), region.used());
+        assert_same(&region.read_chunk(a).unwrap().unwrap(), &chunk(3));
+        assert_same(&region.read_chunk(b).unwrap().unwrap(), &chunk(2));
+
+        // Writes keep working on the compacted file.
+        region.write_chunk(b, &chunk(4)).unwrap();
+        drop(region);
+        let mut region = RegionFile::open(&path).unwrap();
+        assert_same(&region.read_chunk(a).unwrap().unwrap(), &chunk(3));
+        assert_same(&region.read_chunk(b).unwrap().unwrap(), &chunk(4));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn keeps_the_old_chunk_when_the_table_was_not_updated() {
+        let path = temp_path("interrupted");
+        let pos = Vec2::new(5, 6);
+        let mut region = RegionFile::open(&path).unwrap();
+        region.write_chunk(pos, &chunk(1)).unwrap();
+        drop(region);
+
+        // A crash between appending the new copy and updating the table.
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&codec::encode_chunk(pos, &chunk(2), Compression::Lz4))
+            .unwrap();
+        drop(file);
+
+        let mut region = RegionFile::open(&path).unwrap();
+        assert_same(&region.read_chunk(pos).unwrap().unwrap(), &chunk(1));
+        assert!(region.wasted() > 0);
+        region.write_chunk(pos, &chunk(3)).unwrap();
+        drop(region);
+        let mut region = RegionFile::open(&path).unwrap();
+        assert_same(&region.read_chunk(pos).unwrap().unwrap(), &chunk(3));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rejects_corrupt_offset_entries() {
+        let path = temp_path("corrupt");
+        let (a, b) = (Vec2::new(1, 1), Vec2::new(2, 1));
+        let mut region = RegionFile::open(&path).unwrap();
+        region.write_chunk(a, &chunk(1)).unwrap();
+        drop(region);
+        let len = fs::metadata(&path).unwrap().len() as u32;
+
+        // Past the end of the file.
+        set_entry(
+            &path,
+            b,
+            Entry {
+                offset: len,
+                len: 1,
+            },
+        );
+        assert!(matches!(
+            RegionFile::open(&path),
+            Err(SaveError::InvalidRegion)
+        ));
+        // Overflowing.
+        set_entry(
+            &path,
+            b,
+            Entry {
+                offset: u32::MAX,
+                len: u32::MAX,
+            },
+        );
+        assert!(matches!(
+            RegionFile::open(&path),
+            Err(SaveError::InvalidRegion)
+        ));
+        // Inside the header.
+        set_entry(&path, b, Entry { offset: 0, len: 8 });
+        assert!(matches!(
+            RegionFile::open(&path),
+            Err(SaveError::InvalidRegion)
+        ));
+
+        // Pointing at the copy of another column.
+        set_entry(&path, b, Entry::default());
+        let entry = RegionFile::open(&path).unwrap().entries[slot(a)];
+        set_entry(&path, b, entry);
+        let mut region = RegionFile::open(&path).unwrap();
+        assert!(matches!(
+            region.read_chunk(b),
+            Err(SaveError::InvalidRegion)
+        ));
+        // Pointing at a part of a chunk.
+        set_entry(
+            &path,
+            b,
+            Entry {
+                offset: entry.offset + 1,
+                len: entry.len - 1,
+            },
+        );
+        let mut region = RegionFile::open(&path).unwrap();
+        assert!(matches!(region.read_chunk(b), Err(SaveError::Codec(_))));
+        fs::remove_file(&path).unwrap();
+    }
+}
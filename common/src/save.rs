@@ -0,0 +1,320 @@
+//! Persistence of worlds on disk.
+//!
+//! A world is saved in its own directory:
+//!
+//! - `world.ron` holds the [WorldMeta], such as the seed the world is
+//!   generated from and the ids of its blocks.
+//! - `regions/r.<x>.<z>.region` hold the modified chunks, see
+//!   [crate::region].
+//! - `session.lock` is locked for as long as the world is open, so that a
+//!   single process uses it at a time.
+//!
+//! Chunks that were never modified are not saved, as they can be generated
+//! again from the seed.
+
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    fs::{self, File, TryLockError},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    block::{BlockRegistry, BlockRegistryError},
+    chunk::Chunk,
+    codec::ChunkCodecError,
+    math::Vec2,
+    region::RegionFile,
+};
+
+/// Version of the world directory layout.
+pub const WORLD_VERSION: u32 = 1;
+
+const META_FILE: &str = "world.ron";
+const REGIONS_DIR: &str = "regions";
+const LOCK_FILE: &str = "session.lock";
+
+#[derive(Debug)]
+pub enum SaveError {
+    Io(std::io::ErrorKind),
+    /// The world metadata could not be read.
+    Metadata(ron::error::SpannedError),
+    /// The world metadata could not be written.
+    Serialize(ron::Error),
+    UnsupportedVersion(u32),
+    /// A region file is corrupted.
+    InvalidRegion,
+    /// A region file reached the maximum size its offset table can address.
+    RegionFull,
+    Codec(ChunkCodecError),
+    /// The blocks the world was saved with are not all defined.
+    Blocks(BlockRegistryError),
+    /// The world is already open, by this or another process.
+    Locked,
+}
+
+impl From<std::io::Error> for SaveError {
+    fn from(value: std::io::Error) -> Self {
+        SaveError::Io(value.kind())
+    }
+}
+
+impl From<ChunkCodecError> for SaveError {
+    fn from(value: ChunkCodecError) -> Self {
+        SaveError::Codec(value)
+    }
+}
+
+impl From<BlockRegistryError> for SaveError {
+    fn from(value: BlockRegistryError) -> Self {
+        SaveError::Blocks(value)
+    }
+}
+
+/// General information about a saved world.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WorldMeta {
+    /// Version of the layout the world was saved with.
+    pub version: u32,
+    /// Seed of the world generator.
+    pub seed: u32,
+    /// Names of the blocks in id order, as the chunks store ids.
+    #[serde(default)]
+    pub blocks: Vec<String>,
+}
+
+/// A world directory on disk.
+///
+/// Region files are opened on demand and kept open. Every method can be
+/// called from any thread; calls are serialized.
+pub struct WorldSave {
+    dir: PathBuf,
+    /// Holds the lock on the world until the save is dropped.
+    _lock: File,
+    meta: WorldMeta,
+    regions: Mutex<HashMap<Vec2<i32>, RegionFile>>,
+}
+
+impl WorldSave {
+    /// Opens the world saved in `dir`, or creates a new one generated from
+    /// `seed` if there is none.
+    ///
+    /// Fails with [`SaveError::Locked`] if the world is already open.
+    ///
+    /// Returns the world along with `blocks` remapped to the ids the world
+    /// was saved with, see [`BlockRegistry::remap`]. Blocks defined since
+    /// are added to the world.
+    pub fn open(
+        dir: impl AsRef<Path>,
+        seed: u32,
+        blocks: &BlockRegistry,
+    ) -> Result<(Self, BlockRegistry), SaveError> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(dir.join(REGIONS_DIR))?;
+        let lock = File::create(dir.join(LOCK_FILE))?;
+        match lock.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => return Err(SaveError::Locked),
+            Err(TryLockError::Error(e)) => return Err(e.into()),
+        }
+
+        let meta_path = dir.join(META_FILE);
+        let mut meta = if meta_path.exists() {
+            let meta: WorldMeta =
+                ron::from_str(&fs::read_to_string(&meta_path)?).map_err(SaveError::Metadata)?;
+            if meta.version != WORLD_VERSION {
+                return Err(SaveError::UnsupportedVersion(meta.version));
+            }
+            meta
+        } else {
+            tracing::info!("Creating a new world in {}", dir.display());
+            WorldMeta {
+                version: WORLD_VERSION,
+                seed,
+                blocks: Vec::new(),
+            }
+        };
+
+        let blocks = blocks.remap(&meta.blocks)?;
+        if blocks.len() != meta.blocks.len() || !meta_path.exists() {
+            meta.blocks = blocks.names();
+            let contents = ron::ser::to_string_pretty(&meta, Default::default())
+                .map_err(SaveError::Serialize)?;
+            write_atomic(&meta_path, |file| file.write_all(contents.as_bytes()))?;
+        }
+
+        let save = Self {
+            dir,
+            _lock: lock,
+            meta,
+            regions: Mutex::new(HashMap::new()),
+        };
+        Ok((save, blocks))
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn meta(&self) -> &WorldMeta {
+        &self.meta
+    }
+
+    pub fn seed(&self) -> u32 {
+        self.meta.seed
+    }
+
+    /// Loads the chunk at the given column, or `None` if it was never saved.
+    pub fn load_chunk(&self, pos: Vec2<i32>) -> Result<Option<Chunk>, SaveError> {
+        let region_pos = RegionFile::region_pos(pos);
+        let region_path = self.region_path(region_pos);
+        let mut regions = self.regions.lock().unwrap();
+        if !regions.contains_key(&region_pos) && !region_path.exists() {
+            return Ok(None);
+        }
+        self.region(&mut regions, region_pos)?.read_chunk(pos)
+    }
+
+    /// Saves the chunk at the given column.
+    pub fn save_chunk(&self, pos: Vec2<i32>, chunk: &Chunk) -> Result<(), SaveError> {
+        let mut regions = self.regions.lock().unwrap();
+        self.region(&mut regions, RegionFile::region_pos(pos))?
+            .write_chunk(pos, chunk)
+    }
+
+    /// Returns the open region file at the given position, opening it first
+    /// if needed.
+    fn region<'a>(
+        &self,
+        regions: &'a mut HashMap<Vec2<i32>, RegionFile>,
+        pos: Vec2<i32>,
+    ) -> Result<&'a mut RegionFile, SaveError> {
+        Ok(match regions.entry(pos) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(RegionFile::open(self.region_path(pos))?),
+        })
+    }
+
+    fn region_path(&self, pos: Vec2<i32>) -> PathBuf {
+        self.dir
+            .join(REGIONS_DIR)
+            .join(format!("r.{}.{}.region", pos.x, pos.y))
+    }
+}
+
+/// Replaces the file at `path` with the contents written by `write`.
+///
+/// The contents are written to a temporary file which is then renamed over
+/// `path`, so the file is never left half written.
+pub(crate) fn write_atomic(
+    path: &Path,
+    write: impl FnOnce(&mut File) -> std::io::Result<()>,
+) -> std::io::Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+
+    let mut file = File::create(&tmp_path)?;
+    write(&mut file)?;
+    file.sync_all()?;
+    drop(file);
+    fs::rename(&tmp_path, path)?;
+    // Make the rename itself durable. Directories cannot be opened on every
+    // platform, in which case this is best effort.
+    if let Some(dir) = path.parent().and_then(|dir| File::open(dir).ok()) {
+        let _ = dir.sync_all();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        block::{BlockDef, BlockId},
+        math::Vec3,
+    };
+
+    /// A directory in the temporary directory that no other test uses.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("explora-save-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn blocks(names: &[&str]) -> BlockRegistry {
+        BlockRegistry::from_defs(names.iter().map(|name| BlockDef::test(name)).collect()).unwrap()
+    }
+
+    #[test]
+    fn round_trips_chunks() {
+        let dir = temp_dir("round-trip");
+        let pos = Vec2::new(-40, 70);
+        let (save, _) = WorldSave::open(&dir, 9, &blocks(&["stone"])).unwrap();
+        assert_eq!(save.seed(), 9);
+        assert!(save.load_chunk(pos).unwrap().is_none());
+
+        let mut chunk = Chunk::empty();
+        chunk.set(Vec3::new(1, 2, 3), BlockId::from_raw(1));
+        save.save_chunk(pos, &chunk).unwrap();
+        drop(save);
+
+        // The seed of existing worlds is kept.
+        let (save, _) = WorldSave::open(&dir, 10, &blocks(&["stone"])).unwrap();
+        assert_eq!(save.seed(), 9);
+        let chunk = save.load_chunk(pos).unwrap().unwrap();
+        assert_eq!(chunk.get(Vec3::new(1, 2, 3)), Some(BlockId::from_raw(1)));
+        assert!(save.load_chunk(pos + Vec2::unit_x()).unwrap().is_none());
+        drop(save);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn refuses_worlds_already_open() {
+        let dir = temp_dir("locked");
+        let blocks = blocks(&["stone"]);
+        let save = WorldSave::open(&dir, 0, &blocks).unwrap();
+        assert!(matches!(
+            WorldSave::open(&dir, 0, &blocks),
+            Err(SaveError::Locked)
+        ));
+        drop(save);
+        assert!(WorldSave::open(&dir, 0, &blocks).is_ok());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn remaps_blocks_to_the_saved_ids() {
+        let dir = temp_dir("remap");
+        let (save, created) = WorldSave::open(&dir, 0, &blocks(&["dirt", "stone"])).unwrap();
+        assert_eq!(save.meta().blocks, ["air", "dirt", "stone"]);
+        let stone = created.id("stone").unwrap();
+        drop(save);
+
+        // Definitions loaded in another order, with a new block.
+        let reordered = blocks(&["stone", "sand", "dirt"]);
+        assert_ne!(reordered.id("stone"), Some(stone));
+        let (save, remapped) = WorldSave::open(&dir, 0, &reordered).unwrap();
+        assert_eq!(remapped.names(), ["air", "dirt", "stone", "sand"]);
+        assert_eq!(remapped.id("stone"), Some(stone));
+        assert_eq!(save.meta().blocks, remapped.names());
+        drop(save);
+
+        // The new block keeps its id once recorded.
+        let (save, remapped) =
+            WorldSave::open(&dir, 0, &blocks(&["sand", "stone", "dirt"])).unwrap();
+        assert_eq!(remapped.names(), ["air", "dirt", "stone", "sand"]);
+        drop(save);
+
+        // Blocks the world uses must be defined.
+        assert!(matches!(
+            WorldSave::open(&dir, 0, &blocks(&["stone", "sand"])),
+            Err(SaveError::Blocks(BlockRegistryError::UnknownBlock(name))) if name == "dirt"
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,166 @@
+use noise::{Fbm, MultiFractal, NoiseFn, Perlin};
+
+use crate::{
+    block::{BlockId, BlockRegistry},
+    chunk::Chunk,
+    math::{Vec2, Vec3},
+    world::World,
+};
+
+/// Procedural source of terrain.
+///
+/// Implementations must be pure: generating the same position with the same
+/// seed always yields the same chunk, so the server, the client and tests can
+/// agree on the terrain without exchanging it.
+pub trait WorldGenerator: Send + Sync {
+    /// The seed this generator was created with.
+    fn seed(&self) -> u32;
+
+    /// Generates the chunk column at the given chunk position.
+    fn generate(&self, pos: Vec2<i32>) -> Chunk;
+}
+
+/// Heightmap terrain built from layered gradient noise.
+///
+/// Every column is filled with stone up to a few blocks below the surface,
+/// followed by a layer of dirt and a single block of grass on top.
+pub struct HeightmapGenerator {
+    seed: u32,
+    noise: Fbm<Perlin>,
+    stone: BlockId,
+    dirt: BlockId,
+    grass: BlockId,
+}
+
+impl HeightmapGenerator {
+    /// Height around which the surface oscillates.
+    pub const BASE_HEIGHT: f64 = 64.0;
+    /// Maximum distance of the surface from [`Self::BASE_HEIGHT`].
+    pub const AMPLITUDE: f64 = 24.0;
+    /// Frequency of the first octave, in cycles per block.
+    const FREQUENCY: f64 = 1.0 / 128.0;
+    const OCTAVES: usize = 5;
+    /// Thickness of the dirt layer, including the grass block.
+    const DIRT_DEPTH: i32 = 4;
+
+    pub fn new(seed: u32, blocks: &BlockRegistry) -> Self {
+        let noise = Fbm::<Perlin>::new(seed)
+            .set_octaves(Self::OCTAVES)
+            .set_frequency(Self::FREQUENCY);
+        let block = |name| {
+            blocks.id(name).unwrap_or_else(|| {
+                tracing::warn!("Block used by the terrain generator not found: {}", name);
+                BlockId::AIR
+            })
+        };
+        Self {
+            seed,
+            noise,
+            stone: block("stone"),
+            dirt: block("dirt"),
+            grass: block("grass"),
+        }
+    }
+
+    /// Returns the height of the surface block at the given world column.
+    pub fn height(&self, x: i32, z: i32) -> i32 {
+        let value = self.noise.get([x as f64, z as f64]);
+        let height = Self::BASE_HEIGHT + value * Self::AMPLITUDE;
+        (height.round() as i32).clamp(0, Chunk::SIZE.y as i32 - 1)
+    }
+}
+
+impl WorldGenerator for HeightmapGenerator {
+    fn seed(&self) -> u32 {
+        self.seed
+    }
+
+    fn generate(&self, pos: Vec2<i32>) -> Chunk {
+        let mut chunk = Chunk::empty();
+        for x in 0..Chunk::SIZE.x as i32 {
+            for z in 0..Chunk::SIZE.z as i32 {
+                let column = World::block_pos(pos, Vec3::new(x, 0, z));
+                let height = self.height(column.x, column.z);
+                for y in 0..=height {
+                    let block = if y == height {
+                        self.grass
+                    } else if y > height - Self::DIRT_DEPTH {
+                        self.dirt
+                    } else {
+                        self.stone
+                    };
+                    chunk.set(Vec3::new(x, y, z), block);
+                }
+            }
+        }
+        chunk.compact();
+        chunk
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::BlockDef;
+
+    fn blocks() -> BlockRegistry {
+        let defs = ["dirt", "grass", "stone"].map(BlockDef::test);
+        BlockRegistry::from_defs(defs.into()).unwrap()
+    }
+
+    /// Every block of the chunk, in a fixed order.
+    fn contents(chunk: &Chunk) -> Vec<BlockId> {
+        let mut blocks = Vec::with_capacity(Chunk::VOLUME);
+        for x in 0..Chunk::SIZE.x as i32 {
+            for y in 0..Chunk::SIZE.y as i32 {
+                for z in 0..Chunk::SIZE.z as i32 {
+                    blocks.push(chunk.get(Vec3::new(x, y, z)).unwrap());
+                }
+            }
+        }
+        blocks
+    }
+
+    #[test]
+    fn generation_is_deterministic() {
+        let blocks = blocks();
+        let pos = Vec2::new(3, -2);
+        let a = HeightmapGenerator::new(42, &blocks).generate(pos);
+        let b = HeightmapGenerator::new(42, &blocks).generate(pos);
+        assert_eq!(contents(&a), contents(&b));
+
+        let other = HeightmapGenerator::new(43, &blocks).generate(pos);
+        assert_ne!(contents(&a), contents(&other));
+    }
+
+    #[test]
+    fn columns_are_layered() {
+        let blocks = blocks();
+        let (stone, dirt, grass) = (
+            blocks.id("stone").unwrap(),
+            blocks.id("dirt").unwrap(),
+            blocks.id("grass").unwrap(),
+        );
+        let generator = HeightmapGenerator::new(7, &blocks);
+        let pos = Vec2::new(-1, 5);
+        let chunk = generator.generate(pos);
+        for x in 0..Chunk::SIZE.x as i32 {
+            for z in 0..Chunk::SIZE.z as i32 {
+                let column = World::block_pos(pos, Vec3::new(x, 0, z));
+                let height = generator.height(column.x, column.z);
+                for y in 0..Chunk::SIZE.y as i32 {
+                    let expected = if y > height {
+                        BlockId::AIR
+                    } else if y == height {
+                        grass
+                    } else if y > height - HeightmapGenerator::DIRT_DEPTH {
+                        dirt
+                    } else {
+                        stone
+                    };
+                    assert_eq!(chunk.get(Vec3::new(x, y, z)), Some(expected));
+                }
+            }
+        }
+    }
+}
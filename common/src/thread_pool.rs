@@ -0,0 +1,72 @@
+use std::{
+    sync::{mpsc, Arc, Mutex},
+    thread::JoinHandle,
+};
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// A fixed set of worker threads executing jobs from a shared queue.
+///
+/// Used to keep expensive work such as chunk generation and meshing out of
+/// the render and tick threads. Results are expected to be sent back by the jobs
+/// themselves (e.g. through a channel).
+pub struct ThreadPool {
+    sender: Option<mpsc::Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl ThreadPool {
+    /// Creates a pool with the given amount of worker threads.
+    pub fn new(threads: usize) -> Self {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..threads.max(1))
+            .map(|i| {
+                let receiver = Arc::clone(&receiver);
+                std::thread::Builder::new()
+                    .name(format!("worker-{}", i))
+                    .spawn(move || loop {
+                        // The lock is released as soon as a job is received, so
+                        // other workers can pick up jobs while this one runs.
+                        let job = receiver.lock().unwrap().recv();
+                        match job {
+                            Ok(job) => job(),
+                            // The pool was dropped.
+                            Err(_) => break,
+                        }
+                    })
+                    .unwrap()
+            })
+            .collect();
+        tracing::info!("Thread pool started with {} workers.", threads.max(1));
+        Self {
+            sender: Some(sender),
+            workers,
+        }
+    }
+
+    /// Creates a pool with one worker per available core, leaving one core for
+    /// the main thread.
+    pub fn with_available_parallelism() -> Self {
+        let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+        Self::new(cores.saturating_sub(1))
+    }
+
+    /// Queues a job to be executed by the first available worker.
+    pub fn execute<F: FnOnce() + Send + 'static>(&self, job: F) {
+        if let Some(sender) = &self.sender {
+            // Workers only stop once the sender is dropped, so this can't fail.
+            let _ = sender.send(Box::new(job));
+        }
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        // Closing the channel makes the workers exit once the queue is empty.
+        self.sender.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
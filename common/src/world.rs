@@ -0,0 +1,203 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use crate::{
+    block::BlockId,
+    chunk::Chunk,
+    math::{Vec2, Vec3},
+};
+
+/// Storage for the loaded part of an (infinite) world.
+///
+/// Chunks are owned by their column position, that is, the `x` and `z`
+/// coordinates of the chunk in chunk units. Block positions are expressed in
+/// world coordinates and converted to a `(chunk, local)` pair on access.
+///
+/// Chunks are reference counted so they can be cheaply handed out to other
+/// threads (e.g. for meshing). Mutating a shared chunk clones it first.
+///
+/// Chunks accessed mutably are flagged as modified until
+/// [`World::clear_modified`] is called, so they can be saved.
+#[derive(Default)]
+pub struct World {
+    chunks: HashMap<Vec2<i32>, Arc<Chunk>>,
+    modified: HashSet<Vec2<i32>>,
+}
+
+impl World {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the position of the chunk column that contains the given block.
+    pub fn chunk_pos(pos: Vec3<i32>) -> Vec2<i32> {
+        Vec2::new(
+            pos.x.div_euclid(Chunk::SIZE.x as i32),
+            pos.z.div_euclid(Chunk::SIZE.z as i32),
+        )
+    }
+
+    /// Returns the position of the given block relative to its chunk.
+    pub fn local_pos(pos: Vec3<i32>) -> Vec3<i32> {
+        Vec3::new(
+            pos.x.rem_euclid(Chunk::SIZE.x as i32),
+            pos.y,
+            pos.z.rem_euclid(Chunk::SIZE.z as i32),
+        )
+    }
+
+    /// Converts a world block position into a `(chunk, local)` pair.
+    pub fn split_pos(pos: Vec3<i32>) -> (Vec2<i32>, Vec3<i32>) {
+        (Self::chunk_pos(pos), Self::local_pos(pos))
+    }
+
+    /// Converts a `(chunk, local)` pair back into a world block position.
+    pub fn block_pos(chunk_pos: Vec2<i32>, local: Vec3<i32>) -> Vec3<i32> {
+        Vec3::new(
+            chunk_pos.x * Chunk::SIZE.x as i32 + local.x,
+            local.y,
+            chunk_pos.y * Chunk::SIZE.z as i32 + local.z,
+        )
+    }
+
+    /// Inserts a chunk, returning the one previously stored at `pos`, if any.
+    pub fn insert_chunk(
+        &mut self,
+        pos: Vec2<i32>,
+        chunk: impl Into<Arc<Chunk>>,
+    ) -> Option<Arc<Chunk>> {
+        self.modified.remove(&pos);
+        self.chunks.insert(pos, chunk.into())
+    }
+
+    pub fn remove_chunk(&mut self, pos: Vec2<i32>) -> Option<Arc<Chunk>> {
+        self.modified.remove(&pos);
+        self.chunks.remove(&pos)
+    }
+
+    pub fn chunk(&self, pos: Vec2<i32>) -> Option<&Arc<Chunk>> {
+        self.chunks.get(&pos)
+    }
+
+    /// Returns the chunk at `pos` for editing, flagging it as modified.
+    pub fn chunk_mut(&mut self, pos: Vec2<i32>) -> Option<&mut Chunk> {
+        let chunk = self.chunks.get_mut(&pos)?;
+        self.modified.insert(pos);
+        Some(Arc::make_mut(chunk))
+    }
+
+    /// Whether the chunk at `pos` was modified since it was loaded or last
+    /// saved.
+    pub fn is_modified(&self, pos: Vec2<i32>) -> bool {
+        self.modified.contains(&pos)
+    }
+
+    /// Positions of the chunks modified since they were loaded or last saved.
+    pub fn modified(&self) -> impl Iterator<Item = Vec2<i32>> + '_ {
+        self.modified.iter().copied()
+    }
+
+    /// Flags the chunk at `pos` as saved.
+    pub fn clear_modified(&mut self, pos: Vec2<i32>) {
+        self.modified.remove(&pos);
+    }
+
+    pub fn contains_chunk(&self, pos: Vec2<i32>) -> bool {
+        self.chunks.contains_key(&pos)
+    }
+
+    pub fn chunks(&self) -> impl Iterator<Item = (Vec2<i32>, &Arc<Chunk>)> {
+        self.chunks.iter().map(|(pos, chunk)| (*pos, chunk))
+    }
+
+    /// Number of loaded chunks.
+    pub fn len(&self) -> usize {
+        self.chunks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    /// Returns the chunk at `pos` together with its loaded neighbours.
+    pub fn neighbourhood(&self, pos: Vec2<i32>) -> Option<ChunkNeighbourhood> {
+        if !self.contains_chunk(pos) {
+            return None;
+        }
+        let mut chunks: [Option<Arc<Chunk>>; 9] = Default::default();
+        for z in -1..=1 {
+            for x in -1..=1 {
+                let offset = Vec2::new(x, z);
+                chunks[ChunkNeighbourhood::slot(offset).unwrap()] =
+                    self.chunk(pos + offset).cloned();
+            }
+        }
+        Some(ChunkNeighbourhood { pos, chunks })
+    }
+
+    /// Returns the block at the given world position.
+    ///
+    /// Returns `None` if the containing chunk is not loaded or the position is
+    /// outside of the vertical bounds of the world.
+    pub fn get_block(&self, pos: Vec3<i32>) -> Option<BlockId> {
+        let (chunk_pos, local) = Self::split_pos(pos);
+        self.chunk(chunk_pos)?.get(local)
+    }
+
+    /// Sets the block at the given world position, returning the previous one.
+    ///
+    /// Returns `None` and does nothing if the containing chunk is not loaded or
+    /// the position is outside of the vertical bounds of the world.
+    pub fn set_block(&mut self, pos: Vec3<i32>, block: BlockId) -> Option<BlockId> {
+        let (chunk_pos, local) = Self::split_pos(pos);
+        self.chunk_mut(chunk_pos)?.set(local, block)
+    }
+}
+
+/// A chunk together with the chunks surrounding it.
+///
+/// Allows looking up blocks slightly outside of a chunk, as needed when
+/// meshing its borders, without holding on to the whole [World].
+#[derive(Clone)]
+pub struct ChunkNeighbourhood {
+    pos: Vec2<i32>,
+    /// The 3x3 grid of chunks centered on `pos`, indexed by
+    /// [`ChunkNeighbourhood::slot`]. Missing neighbours are not loaded.
+    chunks: [Option<Arc<Chunk>>; 9],
+}
+
+impl ChunkNeighbourhood {
+    /// Position of the center chunk.
+    pub fn pos(&self) -> Vec2<i32> {
+        self.pos
+    }
+
+    pub fn center(&self) -> &Chunk {
+        self.chunks[4]
+            .as_deref()
+            .expect("neighbourhood is always centered on a loaded chunk")
+    }
+
+    /// Returns the block at the given position, relative to the center chunk.
+    ///
+    /// Positions up to one chunk away horizontally are resolved through the
+    /// neighbouring chunks. Returns `None` if that chunk is not loaded or the
+    /// position is outside of the vertical bounds of the world.
+    pub fn get(&self, pos: Vec3<i32>) -> Option<BlockId> {
+        let offset = Vec2::new(
+            pos.x.div_euclid(Chunk::SIZE.x as i32),
+            pos.z.div_euclid(Chunk::SIZE.z as i32),
+        );
+        let chunk = self.chunks[Self::slot(offset)?].as_ref()?;
+        chunk.get(World::local_pos(pos))
+    }
+
+    fn slot(offset: Vec2<i32>) -> Option<usize> {
+        if offset.x.abs() > 1 || offset.y.abs() > 1 {
+            return None;
+        }
+        Some(((offset.y + 1) * 3 + offset.x + 1) as usize)
+    }
+}
@@ -1,4 +1,7 @@
-use common::math::{Mat4f, Vec2, Vec3};
+use common::{
+    math::{Mat4f, Vec2, Vec3, Vec4},
+    world::World,
+};
 use std::f32;
 
 const NEAR_PLANE: f32 = 0.1;
@@ -6,7 +9,61 @@ const FAR_PLANE: f32 = 1000.0;
 
 pub struct Matrices {
     pub proj: Mat4f,
+    /// Relative to `origin`, so that positions stay small enough for `f32`
+    /// far away from the origin of the world.
     pub view: Mat4f,
+    /// Origin of the chunk the camera is in.
+    pub origin: Vec3<i32>,
+}
+
+impl Matrices {
+    /// Returns the volume visible through these matrices, relative to
+    /// [`Matrices::origin`].
+    pub fn frustum(&self) -> Frustum {
+        Frustum::from_matrix(self.proj * self.view)
+    }
+}
+
+/// The volume visible by a camera, bounded by six planes.
+#[derive(Clone, Copy, Debug)]
+pub struct Frustum {
+    /// Planes as `(normal, distance)`, with the normal pointing inside.
+    planes: [(Vec3<f32>, f32); 6],
+}
+
+impl Frustum {
+    /// Extracts the planes of a combined projection and view matrix with a
+    /// clip space depth from -1 to 1.
+    pub fn from_matrix(matrix: Mat4f) -> Self {
+        let [r0, r1, r2, r3] = matrix.into_row_arrays().map(Vec4::<f32>::from);
+        let planes = [r3 + r0, r3 - r0, r3 + r1, r3 - r1, r3 + r2, r3 - r2].map(|plane| {
+            let normal = plane.xyz();
+            let len = normal.magnitude();
+            (normal / len, plane.w / len)
+        });
+        Self { planes }
+    }
+
+    /// Returns the planes as `[normal.x, normal.y, normal.z, distance]`, the
+    /// way shaders expect them.
+    pub fn planes(&self) -> [[f32; 4]; 6] {
+        self.planes
+            .map(|(normal, distance)| [normal.x, normal.y, normal.z, distance])
+    }
+
+    /// Whether any part of the given box may be visible.
+    pub fn intersects_aabb(&self, min: Vec3<f32>, max: Vec3<f32>) -> bool {
+        self.planes.iter().all(|(normal, distance)| {
+            // The corner furthest along the normal is the last one to leave
+            // the frustum.
+            let corner = Vec3::new(
+                if normal.x >= 0.0 { max.x } else { min.x },
+                if normal.y >= 0.0 { max.y } else { min.y },
+                if normal.z >= 0.0 { max.z } else { min.z },
+            );
+            normal.dot(corner) + distance >= 0.0
+        })
+    }
 }
 
 pub struct Camera {
@@ -20,7 +77,7 @@ pub struct Camera {
 impl Camera {
     pub fn new(aspect: f32) -> Self {
         Self {
-            pos: Vec3::new(0.0, 260.0, 0.0),
+            pos: Vec3::new(0.0, 100.0, 0.0),
             rotation: Vec2::new(-1.5, 0.0),
             aspect,
             fov: f32::consts::FRAC_PI_2,
@@ -32,6 +89,7 @@ impl Camera {
                     FAR_PLANE,
                 ),
                 view: Mat4f::identity(),
+                origin: Vec3::zero(),
             },
         }
     }
@@ -51,10 +109,14 @@ impl Camera {
     }
 
     pub fn compute_matrices(&mut self) -> Matrices {
-        self.matrices.view = Mat4f::look_at_lh(self.pos, self.pos + self.forward(), Vec3::unit_y());
+        let chunk_pos = World::chunk_pos(self.pos.map(|x| x.floor() as i32));
+        self.matrices.origin = World::block_pos(chunk_pos, Vec3::zero());
+        let eye = self.pos - self.matrices.origin.as_::<f32>();
+        self.matrices.view = Mat4f::look_at_lh(eye, eye + self.forward(), Vec3::unit_y());
         Matrices {
             proj: self.matrices.proj,
             view: self.matrices.view,
+            origin: self.matrices.origin,
         }
     }
 
@@ -62,6 +124,14 @@ impl Camera {
         self.pos += dz * self.forward_xz() + -dx * self.right() + Vec3::unit_y() * dy;
     }
 
+    pub fn pos(&self) -> Vec3<f32> {
+        self.pos
+    }
+
+    pub fn set_pos(&mut self, pos: Vec3<f32>) {
+        self.pos = pos;
+    }
+
     pub fn right(&self) -> Vec3<f32> {
         self.forward().cross(Vec3::unit_y()).normalized()
     }
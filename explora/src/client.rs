@@ -0,0 +1,241 @@
+use std::{
+    net::ToSocketAddrs,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use common::{
+    block::{BlockId, BlockRegistry, BlockRegistryError},
+    math::Vec3,
+    net::{ClientConnection, TcpConnection},
+    protocol::{ClientMessage, ProtocolError, ServerMessage, PROTOCOL_VERSION},
+    save::SaveError,
+};
+
+use crate::terrain::Terrain;
+
+/// Time the server has to accept the handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Minimum time between two position updates sent to the server.
+const POSITION_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Debug)]
+pub enum ClientError {
+    Io(std::io::ErrorKind),
+    Protocol(ProtocolError),
+    /// The server refused the connection, with the given reason.
+    Refused(String),
+    /// The server did not answer the handshake in time.
+    TimedOut,
+    /// The server answered the handshake with something else.
+    UnexpectedMessage,
+    /// The world of the integrated server could not be opened.
+    Save(SaveError),
+    /// The server uses blocks that are not defined locally.
+    Blocks(BlockRegistryError),
+}
+
+impl From<std::io::Error> for ClientError {
+    fn from(value: std::io::Error) -> Self {
+        ClientError::Io(value.kind())
+    }
+}
+
+impl From<SaveError> for ClientError {
+    fn from(value: SaveError) -> Self {
+        ClientError::Save(value)
+    }
+}
+
+impl From<BlockRegistryError> for ClientError {
+    fn from(value: BlockRegistryError) -> Self {
+        ClientError::Blocks(value)
+    }
+}
+
+impl From<ProtocolError> for ClientError {
+    fn from(value: ProtocolError) -> Self {
+        ClientError::Protocol(value)
+    }
+}
+
+/// The connection of the game to a server.
+pub struct Client {
+    connection: Box<ClientConnection>,
+    seed: u32,
+    /// The local block definitions, with the ids used by the server.
+    blocks: Arc<BlockRegistry>,
+    /// Why the connection was closed, once it is.
+    disconnect_reason: Option<String>,
+    last_sent_pos: Option<Vec3<f32>>,
+    last_position_update: Instant,
+}
+
+impl Client {
+    /// Connects to the server at the given address and joins it with the
+    /// given player name.
+    pub fn connect(
+        addr: impl ToSocketAddrs,
+        name: &str,
+        blocks: &BlockRegistry,
+    ) -> Result<Self, ClientError> {
+        Self::new(Box::new(TcpConnection::connect(addr)?), name, blocks)
+    }
+
+    /// Joins a server over an established connection, blocking until the
+    /// server accepts the handshake.
+    ///
+    /// `blocks` are remapped to the ids used by the server, which fails if
+    /// the server uses blocks that are not defined in it.
+    pub fn new(
+        mut connection: Box<ClientConnection>,
+        name: &str,
+        blocks: &BlockRegistry,
+    ) -> Result<Self, ClientError> {
+        connection.send(ClientMessage::Handshake {
+            protocol_version: PROTOCOL_VERSION,
+            name: name.to_owned(),
+        })?;
+        let start = Instant::now();
+        let (seed, names) = loop {
+            connection.flush()?;
+            match connection.try_recv()? {
+                Some(ServerMessage::HandshakeAccepted { seed, blocks }) => break (seed, blocks),
+                Some(ServerMessage::Disconnect { reason }) => {
+                    return Err(ClientError::Refused(reason))
+                }
+                Some(_) => return Err(ClientError::UnexpectedMessage),
+                None if start.elapsed() > HANDSHAKE_TIMEOUT => return Err(ClientError::TimedOut),
+                None => std::thread::sleep(Duration::from_millis(10)),
+            }
+        };
+        let blocks = match blocks.remap(&names) {
+            Ok(blocks) => Arc::new(blocks),
+            Err(e) => {
+                let _ = connection
+                    .send(ClientMessage::Disconnect {
+                        reason: format!("Unsupported blocks: {:?}", e),
+                    })
+                    .and_then(|()| connection.flush());
+                connection.close();
+                return Err(e.into());
+            }
+        };
+        tracing::info!("Joined {} as {}", connection.peer(), name);
+
+        Ok(Self {
+            connection,
+            seed,
+            blocks,
+            disconnect_reason: None,
+            last_sent_pos: None,
+            last_position_update: start,
+        })
+    }
+
+    /// Seed of the world hosted by the server.
+    pub fn seed(&self) -> u32 {
+        self.seed
+    }
+
+    /// The blocks of the world, numbered like on the server.
+    pub fn blocks(&self) -> &Arc<BlockRegistry> {
+        &self.blocks
+    }
+
+    /// Why the connection was closed, or `None` while it is open.
+    pub fn disconnect_reason(&self) -> Option<&str> {
+        self.disconnect_reason.as_deref()
+    }
+
+    /// Applies the updates received from the server to the terrain and
+    /// sends the position of the player.
+    ///
+    /// Returns the position the server moved the player to, if it did.
+    pub fn tick(&mut self, terrain: &mut Terrain, pos: Vec3<f32>) -> Option<Vec3<f32>> {
+        if self.disconnect_reason.is_some() {
+            return None;
+        }
+        let mut teleport = None;
+        loop {
+            let message = match self.connection.try_recv() {
+                Ok(Some(message)) => message,
+                Ok(None) => break,
+                Err(e) => {
+                    self.closed(format!("Connection error: {:?}", e));
+                    return None;
+                }
+            };
+            match message {
+                ServerMessage::ChunkData { pos, chunk } => terrain.insert_chunk(pos, chunk),
+                ServerMessage::UnloadChunk { pos } => terrain.remove_chunk(pos),
+                ServerMessage::BlockChange { pos, block } => {
+                    terrain.set_block(pos, block);
+                }
+                ServerMessage::PlayerPosition { pos } => teleport = Some(pos),
+                ServerMessage::Chat { message } => tracing::info!("[chat] {}", message),
+                ServerMessage::KeepAlive { id } => self.send(ClientMessage::KeepAlive { id }),
+                ServerMessage::Disconnect { reason } => {
+                    self.closed(reason);
+                    return None;
+                }
+                ServerMessage::HandshakeAccepted { .. } => {
+                    tracing::warn!("Received a second handshake answer");
+                }
+            }
+        }
+
+        let pos = teleport.unwrap_or(pos);
+        if self.last_sent_pos != Some(pos)
+            && (teleport.is_some() || self.last_position_update.elapsed() >= POSITION_INTERVAL)
+        {
+            self.send(ClientMessage::PlayerPosition { pos });
+            self.last_sent_pos = Some(pos);
+            self.last_position_update = Instant::now();
+        }
+        if let Err(e) = self.connection.flush() {
+            self.closed(format!("Connection error: {:?}", e));
+        }
+        teleport
+    }
+
+    /// Changes a block, right away in the terrain and then on the server.
+    /// If the server refuses the change, it sends back the actual block.
+    ///
+    /// Returns the previous block, or `None` if the chunk is not loaded.
+    pub fn set_block(
+        &mut self,
+        terrain: &mut Terrain,
+        pos: Vec3<i32>,
+        block: BlockId,
+    ) -> Option<BlockId> {
+        let previous = terrain.set_block(pos, block)?;
+        self.send(ClientMessage::SetBlock { pos, block });
+        Some(previous)
+    }
+
+    /// Leaves the server.
+    pub fn disconnect(&mut self, reason: &str) {
+        if self.disconnect_reason.is_some() {
+            return;
+        }
+        self.send(ClientMessage::Disconnect {
+            reason: reason.to_owned(),
+        });
+        let _ = self.connection.flush();
+        self.connection.close();
+        self.disconnect_reason = Some(reason.to_owned());
+    }
+
+    fn send(&mut self, message: ClientMessage) {
+        if let Err(e) = self.connection.send(message) {
+            self.closed(format!("Connection error: {:?}", e));
+        }
+    }
+
+    fn closed(&mut self, reason: String) {
+        tracing::info!("Disconnected from the server: {}", reason);
+        self.connection.close();
+        self.disconnect_reason.get_or_insert(reason);
+    }
+}
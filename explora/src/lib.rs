@@ -1,5 +1,9 @@
 pub mod camera;
+pub mod client;
 pub mod key_state;
+pub mod player;
 pub mod render;
 pub mod scene;
+pub mod singleplayer;
+pub mod terrain;
 pub mod window;
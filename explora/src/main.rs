@@ -1,8 +1,52 @@
-use explora::window::Window;
+use common::block::BlockRegistry;
+use explora::{client::Client, singleplayer::Singleplayer, window::Window};
+
+/// Name used on servers when none is given.
+const DEFAULT_NAME: &str = "player";
 
 fn main() {
     common_log::init();
-    let mut window = Window::new();
+
+    let mut server = None;
+    let mut name = DEFAULT_NAME.to_owned();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match (arg.as_str(), args.next()) {
+            ("--connect", Some(addr)) => server = Some(addr),
+            ("--name", Some(value)) => name = value,
+            _ => {
+                eprintln!("Usage: explora [--connect <host:port>] [--name <name>]");
+                return;
+            }
+        }
+    }
+
+    let blocks = match BlockRegistry::load("assets/blocks/") {
+        Ok(blocks) => blocks,
+        Err(e) => {
+            tracing::error!("Could not load the block definitions: {:?}", e);
+            return;
+        }
+    };
+
+    let (client, singleplayer) = match server {
+        Some(addr) => match Client::connect(&addr, &name, &blocks) {
+            Ok(client) => (client, None),
+            Err(e) => {
+                tracing::error!("Could not join {}: {:?}", addr, e);
+                return;
+            }
+        },
+        None => match Singleplayer::start(&name, &blocks) {
+            Ok((singleplayer, client)) => (client, Some(singleplayer)),
+            Err(e) => {
+                tracing::error!("Could not start the world: {:?}", e);
+                return;
+            }
+        },
+    };
+
+    let mut window = Window::new(client, singleplayer);
     window.grab_cursor(true);
     window.run();
 }
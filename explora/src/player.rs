@@ -0,0 +1,243 @@
+use common::math::{Vec2, Vec3};
+
+/// Width of the player on the horizontal axes, in blocks.
+const WIDTH: f32 = 0.6;
+/// Height of the player, in blocks.
+const HEIGHT: f32 = 1.8;
+/// Height of the eyes above the feet, where the camera is.
+pub const EYE_HEIGHT: f32 = 1.62;
+// TODO: make this configurable
+const WALK_SPEED: f32 = 4.3;
+// TODO: make this configurable
+const FLY_SPEED: f32 = 7.0;
+const GRAVITY: f32 = 28.0;
+/// Vertical speed given by a jump, enough to climb a bit more than a block.
+const JUMP_SPEED: f32 = 8.5;
+/// Fastest vertical speed reached when falling.
+const TERMINAL_SPEED: f32 = 60.0;
+/// Highest obstacle climbed without jumping.
+const STEP_HEIGHT: f32 = 1.0;
+/// Longest step simulated at once, so a slow frame does not make the player
+/// fall through the ground.
+const MAX_DT: f32 = 0.05;
+/// Distance kept between the player and the blocks it touches, so touching
+/// faces are not considered overlapping.
+const EPSILON: f32 = 1e-3;
+
+/// An axis-aligned bounding box.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3<f32>,
+    pub max: Vec3<f32>,
+}
+
+impl Aabb {
+    pub fn translated(self, offset: Vec3<f32>) -> Self {
+        Self {
+            min: self.min + offset,
+            max: self.max + offset,
+        }
+    }
+
+    /// Whether the box overlaps the given block.
+    pub fn intersects_block(&self, pos: Vec3<i32>) -> bool {
+        let pos = pos.as_::<f32>();
+        (0..3).all(|axis| self.min[axis] < pos[axis] + 1.0 && self.max[axis] > pos[axis])
+    }
+
+    /// Positions of the blocks overlapped by the box.
+    pub fn blocks(&self) -> impl Iterator<Item = Vec3<i32>> {
+        let min = self.min.map(|x| x.floor() as i32);
+        let max = self.max.map(|x| x.ceil() as i32 - 1);
+        (min.x..=max.x).flat_map(move |x| {
+            (min.y..=max.y).flat_map(move |y| (min.z..=max.z).map(move |z| Vec3::new(x, y, z)))
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MovementMode {
+    /// Gravity applies and blocks can not be walked through.
+    Walk,
+    /// Moves freely in every direction, through blocks.
+    Fly,
+}
+
+/// The player controlled by this client.
+pub struct Player {
+    /// Position of the feet, at the bottom center of the bounding box.
+    pos: Vec3<f32>,
+    velocity: Vec3<f32>,
+    on_ground: bool,
+    mode: MovementMode,
+}
+
+impl Player {
+    pub fn new(pos: Vec3<f32>) -> Self {
+        Self {
+            pos,
+            velocity: Vec3::zero(),
+            on_ground: false,
+            mode: MovementMode::Walk,
+        }
+    }
+
+    pub fn pos(&self) -> Vec3<f32> {
+        self.pos
+    }
+
+    /// Moves the player without checking for collisions.
+    pub fn set_pos(&mut self, pos: Vec3<f32>) {
+        self.pos = pos;
+        self.velocity = Vec3::zero();
+    }
+
+    /// Position of the eyes of the player.
+    pub fn eye_pos(&self) -> Vec3<f32> {
+        self.pos + Vec3::unit_y() * EYE_HEIGHT
+    }
+
+    pub fn on_ground(&self) -> bool {
+        self.on_ground
+    }
+
+    pub fn mode(&self) -> MovementMode {
+        self.mode
+    }
+
+    /// Switches between walking and flying.
+    pub fn toggle_mode(&mut self) {
+        self.mode = match self.mode {
+            MovementMode::Walk => MovementMode::Fly,
+            MovementMode::Fly => MovementMode::Walk,
+        };
+        self.velocity = Vec3::zero();
+        self.on_ground = false;
+    }
+
+    pub fn aabb(&self) -> Aabb {
+        let half = WIDTH / 2.0;
+        Aabb {
+            min: self.pos - Vec3::new(half, 0.0, half),
+            max: self.pos + Vec3::new(half, HEIGHT, half),
+        }
+    }
+
+    /// Advances the player by `dt` seconds.
+    ///
+    /// - `horizontal`: direction to walk or fly in, on the xz plane.
+    /// - `vertical`: positive to jump or fly up, negative to fly down.
+    /// - `is_solid`: whether the player collides with the given block.
+    pub fn tick(
+        &mut self,
+        dt: f32,
+        horizontal: Vec2<f32>,
+        vertical: f32,
+        is_solid: impl Fn(Vec3<i32>) -> bool,
+    ) {
+        let horizontal = horizontal.try_normalized().unwrap_or_default();
+        match self.mode {
+            MovementMode::Fly => {
+                let dir = Vec3::new(horizontal.x, vertical, horizontal.y);
+                self.pos += dir * FLY_SPEED * dt;
+            }
+            MovementMode::Walk => {
+                let mut remaining = dt;
+                while remaining > 0.0 {
+                    let dt = remaining.min(MAX_DT);
+                    self.walk(dt, horizontal, vertical > 0.0, &is_solid);
+                    remaining -= dt;
+                }
+            }
+        }
+    }
+
+    fn walk(
+        &mut self,
+        dt: f32,
+        horizontal: Vec2<f32>,
+        jump: bool,
+        is_solid: &impl Fn(Vec3<i32>) -> bool,
+    ) {
+        self.velocity.x = horizontal.x * WALK_SPEED;
+        self.velocity.z = horizontal.y * WALK_SPEED;
+        if jump && self.on_ground {
+            self.velocity.y = JUMP_SPEED;
+        }
+        self.velocity.y = (self.velocity.y - GRAVITY * dt).max(-TERMINAL_SPEED);
+
+        let delta = self.velocity * dt;
+        let dy = sweep(self.aabb(), 1, delta.y, is_solid);
+        self.pos.y += dy;
+        self.on_ground = delta.y < 0.0 && dy > delta.y;
+        if dy != delta.y {
+            self.velocity.y = 0.0;
+        }
+
+        let moved = self.move_horizontal(self.aabb(), delta, is_solid);
+        if self.on_ground && moved != Vec2::new(delta.x, delta.z) {
+            // Try climbing onto the obstacle, and keep doing so if it gets
+            // further.
+            let up = sweep(self.aabb(), 1, STEP_HEIGHT, is_solid);
+            let raised = self.aabb().translated(Vec3::unit_y() * up);
+            let stepped = self.move_horizontal(raised, delta, is_solid);
+            if stepped.magnitude_squared() > moved.magnitude_squared() + EPSILON {
+                let moved_box = raised.translated(Vec3::new(stepped.x, 0.0, stepped.y));
+                let down = sweep(moved_box, 1, -up, is_solid);
+                self.pos += Vec3::new(stepped.x, up + down, stepped.y);
+                return;
+            }
+        }
+        self.pos += Vec3::new(moved.x, 0.0, moved.y);
+    }
+
+    /// Moves `aabb` along x and then z as far as possible, returning the
+    /// distance travelled on each.
+    fn move_horizontal(
+        &self,
+        aabb: Aabb,
+        delta: Vec3<f32>,
+        is_solid: &impl Fn(Vec3<i32>) -> bool,
+    ) -> Vec2<f32> {
+        let dx = sweep(aabb, 0, delta.x, is_solid);
+        let aabb = aabb.translated(Vec3::unit_x() * dx);
+        let dz = sweep(aabb, 2, delta.z, is_solid);
+        Vec2::new(dx, dz)
+    }
+}
+
+/// Returns how far `aabb` can move by `delta` along `axis` before hitting a
+/// solid block.
+///
+/// Blocks the box already overlaps are ignored, so it can get out of them.
+fn sweep(aabb: Aabb, axis: usize, delta: f32, is_solid: &impl Fn(Vec3<i32>) -> bool) -> f32 {
+    if delta == 0.0 {
+        return 0.0;
+    }
+    let mut swept = aabb;
+    if delta > 0.0 {
+        swept.max[axis] += delta;
+    } else {
+        swept.min[axis] += delta;
+    }
+
+    let mut allowed = delta;
+    for pos in swept.blocks() {
+        if aabb.intersects_block(pos) || !is_solid(pos) {
+            continue;
+        }
+        let start = pos[axis] as f32;
+        if delta > 0.0 {
+            allowed = allowed.min(start - aabb.max[axis] - EPSILON);
+        } else {
+            allowed = allowed.max(start + 1.0 - aabb.min[axis] + EPSILON);
+        }
+    }
+    // Moving back out of a block would mean the box was closer than
+    // EPSILON to it.
+    if allowed.signum() != delta.signum() {
+        0.0
+    } else {
+        allowed
+    }
+}
@@ -1,11 +1,12 @@
 use std::{collections::HashMap, path::Path};
 
-use common::block::BlockId;
+use common::block::{BlockId, BlockRegistry};
 
 use crate::render::png_utils;
 
 use super::png_utils::PngImage;
 
+#[derive(Clone, Copy)]
 pub struct BlockTexture {
     // 0 - North
     // 1 - South
@@ -21,40 +22,38 @@ pub struct Atlas {
     pub image: PngImage,
     pub tile_size: usize,
     textures: HashMap<String, u32>,
+    /// Face textures of every block, indexed by [BlockId].
+    block_textures: Vec<BlockTexture>,
 }
 
 impl Atlas {
+    /// Returns the face textures of the given block.
+    ///
+    /// Unknown blocks use the default texture.
     pub fn block_texture(&self, id: BlockId) -> BlockTexture {
-        // TODO: Temporaal
-        match id {
-            BlockId::Dirt => {
-                let id = self.get("dirt");
-                BlockTexture {
-                    values: [id, id, id, id, id, id],
-                }
-            }
-            BlockId::Grass => {
-                let top = self.get("grass_top");
-                let side = self.get("grass_side");
-                let bottom = self.get("dirt");
-                BlockTexture {
-                    values: [side, side, side, side, top, bottom],
-                }
-            }
-            BlockId::Stone => {
-                let id = self.get("stone");
-                BlockTexture {
-                    values: [id, id, id, id, id, id],
-                }
-            }
-            _ => {
-                let id = self.get("default");
-                BlockTexture {
-                    values: [id, id, id, id, id, id],
+        self.block_textures
+            .get(id.raw() as usize)
+            .copied()
+            .unwrap_or(BlockTexture { values: [0; 6] })
+    }
+
+    /// Resolves the texture names of every block definition into atlas
+    /// texture ids.
+    fn resolve_block_textures(&mut self, blocks: &BlockRegistry) {
+        self.block_textures = blocks
+            .iter()
+            .map(|(_, block)| {
+                let mut values = [0; 6];
+                if let Some(faces) = block.textures.faces() {
+                    for (value, name) in values.iter_mut().zip(faces) {
+                        *value = self.get(name);
+                    }
                 }
-            }
-        }
+                BlockTexture { values }
+            })
+            .collect();
     }
+
     pub fn get(&self, name: &str) -> u32 {
         match self.textures.get(name) {
             Some(id) => *id,
@@ -78,7 +77,10 @@ impl From<std::io::Error> for AtlasError {
 }
 
 impl Atlas {
-    pub fn pack_textures<P: AsRef<Path>>(resource: P) -> Result<Self, AtlasError> {
+    pub fn pack_textures<P: AsRef<Path>>(
+        resource: P,
+        blocks: &BlockRegistry,
+    ) -> Result<Self, AtlasError> {
         let files = std::fs::read_dir(&resource)?
             .map(|x| x.map(|x| x.path()))
             // filter out anything that does not contain a png
@@ -162,7 +164,7 @@ impl Atlas {
             atlas_height as u32,
         )
         .unwrap();
-        Ok(Self {
+        let mut atlas = Self {
             image: PngImage {
                 width: atlas_width as u32,
                 height: atlas_height as u32,
@@ -171,7 +173,10 @@ impl Atlas {
             },
             tile_size: first_image.width as usize,
             textures,
-        })
+            block_textures: Vec::new(),
+        };
+        atlas.resolve_block_textures(blocks);
+        Ok(atlas)
     }
 }
 
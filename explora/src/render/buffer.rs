@@ -32,6 +32,34 @@ impl<T: Copy + bytemuck::Pod> Buffer<T> {
         }
     }
 
+    /// Creates a new [Buffer] able to hold `len` elements, without
+    /// initializing it.
+    pub fn empty(device: &wgpu::Device, usage: wgpu::BufferUsages, len: u32) -> Self {
+        let descriptor = wgpu::BufferDescriptor {
+            label: None,
+            size: len as wgpu::BufferAddress * std::mem::size_of::<T>() as wgpu::BufferAddress,
+            usage,
+            mapped_at_creation: false,
+        };
+        Self {
+            buf: device.create_buffer(&descriptor),
+            phantom: std::marker::PhantomData,
+            len,
+        }
+    }
+
+    /// Write data into the buffer, starting at the element at `offset`.
+    ///
+    /// Like [`Buffer::write`], empty data is ignored.
+    pub fn write_at(&self, queue: &wgpu::Queue, offset: u32, data: &[T]) {
+        if data.is_empty() {
+            return;
+        }
+        let offset =
+            offset as wgpu::BufferAddress * std::mem::size_of::<T>() as wgpu::BufferAddress;
+        queue.write_buffer(&self.buf, offset, bytemuck::cast_slice(data))
+    }
+
     /// Write data into the buffer.
     ///
     /// If the data is empty it will do nothing to avoid
@@ -48,7 +76,7 @@ impl<T: Copy + bytemuck::Pod> Buffer<T> {
         self.buf.slice(..)
     }
 
-    pub fn as_entire_binding(&self) -> wgpu::BindingResource {
+    pub fn as_entire_binding(&self) -> wgpu::BindingResource<'_> {
         self.buf.as_entire_binding()
     }
 
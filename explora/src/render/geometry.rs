@@ -0,0 +1,442 @@
+//! Terrain geometry sub-allocated from a few large shared buffers.
+//!
+//! Every section mesh lives in a page: a vertex buffer shared by many
+//! sections, and a table describing each of them, indexed by slot. Sections
+//! are drawn as a single instance whose index is their slot, so the shader
+//! can find where the section is.
+//!
+//! When the device supports it, a compute pass culls the sections against
+//! the view frustum and writes the arguments of an indirect draw per slot,
+//! so each page is drawn with a single `multi_draw_indexed_indirect` call.
+//! Otherwise sections are culled on the CPU and drawn one by one.
+
+use std::ops::Range;
+
+use common::{chunk::Section, math::Vec3};
+
+use crate::camera::Frustum;
+
+use super::{buffer::Buffer, voxels::DrawStats, Vertex};
+
+/// Vertices held by the vertex buffer of a page.
+const PAGE_VERTICES: u32 = 1 << 22;
+/// Sections held by a page.
+const PAGE_SECTIONS: u32 = 4096;
+/// Must be kept in sync with `cs_main` in `cull.wgsl`.
+const CULL_WORKGROUP_SIZE: u32 = 64;
+
+/// Describes the mesh of a section to the shaders.
+///
+/// Must be kept in sync with `SectionInfo` in `voxels.wgsl` and `cull.wgsl`.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct SectionInfo {
+    /// World position of the origin of the chunk the section belongs to.
+    ///
+    /// Kept as integers, the shaders make it relative to the camera before
+    /// converting it to floats.
+    chunk_origin: [i32; 3],
+    /// Zero if the slot is free.
+    index_count: u32,
+    /// Index of the first vertex of the section in the vertex buffer.
+    base_vertex: i32,
+    /// Index of the section in its chunk.
+    section: u32,
+    _padding: [u32; 2],
+}
+
+/// The view frustum, as seen by `cull.wgsl`.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct CullUniforms {
+    /// See [`Frustum::planes`].
+    planes: [[f32; 4]; 6],
+    /// Origin the planes are relative to.
+    origin: [i32; 3],
+    _padding: u32,
+}
+
+/// Arguments of an indexed indirect draw, written by `cull.wgsl`.
+type DrawIndexedIndirect = [u32; 5];
+
+/// Where the mesh of a section lives in a [GeometryPool].
+#[derive(Debug)]
+pub struct SectionHandle {
+    page: usize,
+    slot: u32,
+    vertices: Range<u32>,
+}
+
+/// Hands out ranges of a buffer, first fit.
+struct RangeAllocator {
+    /// Free ranges, sorted and never adjacent.
+    free: Vec<Range<u32>>,
+}
+
+impl RangeAllocator {
+    fn new(len: u32) -> Self {
+        Self {
+            free: std::iter::once(0..len).collect(),
+        }
+    }
+
+    fn allocate(&mut self, len: u32) -> Option<Range<u32>> {
+        let index = self
+            .free
+            .iter()
+            .position(|range| range.len() >= len as usize)?;
+        let range = &mut self.free[index];
+        let allocated = range.start..range.start + len;
+        range.start += len;
+        if range.start == range.end {
+            self.free.remove(index);
+        }
+        Some(allocated)
+    }
+
+    fn free(&mut self, range: Range<u32>) {
+        let index = self.free.partition_point(|free| free.start < range.start);
+        self.free.insert(index, range);
+        // Merge with the next range, then with the previous one.
+        if index + 1 < self.free.len() && self.free[index].end == self.free[index + 1].start {
+            self.free[index].end = self.free.remove(index + 1).end;
+        }
+        if index > 0 && self.free[index - 1].end == self.free[index].start {
+            self.free[index - 1].end = self.free.remove(index).end;
+        }
+    }
+}
+
+/// A vertex buffer and the sections it holds.
+struct Page {
+    vertices: Buffer<Vertex>,
+    allocator: RangeAllocator,
+    /// The sections in the page, by slot, on the GPU.
+    infos: Buffer<SectionInfo>,
+    /// The sections in the page, by slot.
+    sections: Vec<SectionInfo>,
+    free_slots: Vec<u32>,
+    /// Slots are only used below this one.
+    slot_count: u32,
+    /// One draw per slot, written by the culling pass.
+    draws: Option<Buffer<DrawIndexedIndirect>>,
+    render_bind_group: wgpu::BindGroup,
+    cull_bind_group: Option<wgpu::BindGroup>,
+}
+
+impl Page {
+    fn allocate_slot(&mut self) -> Option<u32> {
+        self.free_slots.pop().or_else(|| {
+            (self.slot_count < PAGE_SECTIONS).then(|| {
+                self.slot_count += 1;
+                self.slot_count - 1
+            })
+        })
+    }
+}
+
+/// Resources of the culling pass, only available on devices able to draw
+/// indirectly.
+struct Culling {
+    pipeline: wgpu::ComputePipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    frustum: Buffer<CullUniforms>,
+}
+
+/// The geometry of every section, ready to be drawn.
+pub struct GeometryPool {
+    pages: Vec<Page>,
+    /// Layout of the section table bound when drawing.
+    render_bind_group_layout: wgpu::BindGroupLayout,
+    culling: Option<Culling>,
+}
+
+impl GeometryPool {
+    /// Creates an empty pool, drawn indirectly if `indirect` is set.
+    ///
+    /// Indirect drawing requires [`wgpu::Features::MULTI_DRAW_INDIRECT`] and
+    /// [`wgpu::Features::INDIRECT_FIRST_INSTANCE`].
+    pub fn new(device: &wgpu::Device, indirect: bool) -> Self {
+        let render_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Section Bind Group Layout"),
+                entries: &[storage_entry(0, wgpu::ShaderStages::VERTEX, true)],
+            });
+        Self {
+            pages: Vec::new(),
+            render_bind_group_layout,
+            culling: indirect.then(|| create_culling(device)),
+        }
+    }
+
+    /// Layout of the bind group holding the section table, in `voxels.wgsl`.
+    pub fn render_bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.render_bind_group_layout
+    }
+
+    /// Whether sections are culled on the GPU and drawn indirectly.
+    pub fn is_indirect(&self) -> bool {
+        self.culling.is_some()
+    }
+
+    /// Uploads the mesh of a section.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the mesh is empty or does not fit in a page.
+    pub fn insert(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        chunk_origin: Vec3<i32>,
+        section: usize,
+        vertices: &[Vertex],
+    ) -> SectionHandle {
+        assert!(!vertices.is_empty() && vertices.len() <= PAGE_VERTICES as usize);
+        let len = vertices.len() as u32;
+        let (page_index, slot, range) = self
+            .pages
+            .iter_mut()
+            .enumerate()
+            .find_map(|(index, page)| {
+                let range = page.allocator.allocate(len)?;
+                match page.allocate_slot() {
+                    Some(slot) => Some((index, slot, range)),
+                    None => {
+                        page.allocator.free(range);
+                        None
+                    }
+                }
+            })
+            .unwrap_or_else(|| {
+                let mut page = self.create_page(device);
+                let range = page.allocator.allocate(len).unwrap();
+                let slot = page.allocate_slot().unwrap();
+                self.pages.push(page);
+                (self.pages.len() - 1, slot, range)
+            });
+
+        let page = &mut self.pages[page_index];
+        page.vertices.write_at(queue, range.start, vertices);
+        let info = SectionInfo {
+            chunk_origin: chunk_origin.into_array(),
+            index_count: len / 4 * 6,
+            base_vertex: range.start as i32,
+            section: section as u32,
+            _padding: [0; 2],
+        };
+        page.sections[slot as usize] = info;
+        page.infos.write_at(queue, slot, &[info]);
+        SectionHandle {
+            page: page_index,
+            slot,
+            vertices: range,
+        }
+    }
+
+    /// Frees the mesh of a section.
+    pub fn remove(&mut self, queue: &wgpu::Queue, handle: SectionHandle) {
+        let page = &mut self.pages[handle.page];
+        page.allocator.free(handle.vertices);
+        page.sections[handle.slot as usize] = SectionInfo::default();
+        page.infos
+            .write_at(queue, handle.slot, &[SectionInfo::default()]);
+        page.free_slots.push(handle.slot);
+    }
+
+    /// Writes the indirect draws of the sections inside the frustum, which is
+    /// relative to `origin`. Does nothing if sections are not drawn
+    /// indirectly.
+    pub fn cull(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        queue: &wgpu::Queue,
+        frustum: &Frustum,
+        origin: Vec3<i32>,
+    ) {
+        let Some(culling) = &self.culling else {
+            return;
+        };
+        culling.frustum.write(
+            queue,
+            &[CullUniforms {
+                planes: frustum.planes(),
+                origin: origin.into_array(),
+                _padding: 0,
+            }],
+        );
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Terrain Culling Pass"),
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&culling.pipeline);
+        for page in &self.pages {
+            pass.set_bind_group(0, page.cull_bind_group.as_ref().unwrap(), &[]);
+            pass.dispatch_workgroups(page.slot_count.div_ceil(CULL_WORKGROUP_SIZE), 1, 1);
+        }
+    }
+
+    /// Draws the sections inside the frustum, which is relative to `origin`,
+    /// with the terrain pipeline and index buffer already set.
+    pub fn draw<'a>(
+        &'a self,
+        frame: &mut wgpu::RenderPass<'a>,
+        frustum: &Frustum,
+        origin: Vec3<i32>,
+        stats: &mut DrawStats,
+    ) {
+        stats.gpu_culling = self.is_indirect();
+        let section_size = Section::SIZE.as_::<f32>();
+        for page in &self.pages {
+            frame.set_bind_group(1, &page.render_bind_group, &[]);
+            frame.set_vertex_buffer(0, page.vertices.slice());
+            if let Some(draws) = &page.draws {
+                if page.slot_count == 0 {
+                    continue;
+                }
+                frame.multi_draw_indexed_indirect(&draws.buf, 0, page.slot_count);
+                stats.drawn += page.slot_count as usize - page.free_slots.len();
+                continue;
+            }
+            for (slot, info) in page.sections.iter().enumerate() {
+                if info.index_count == 0 {
+                    continue;
+                }
+                let min = (Vec3::<i32>::from(info.chunk_origin) - origin).as_::<f32>()
+                    + Vec3::unit_y() * (info.section as f32 * section_size.y);
+                if !frustum.intersects_aabb(min, min + section_size) {
+                    stats.culled += 1;
+                    continue;
+                }
+                let slot = slot as u32;
+                frame.draw_indexed(0..info.index_count, info.base_vertex, slot..slot + 1);
+                stats.drawn += 1;
+            }
+        }
+    }
+
+    fn create_page(&self, device: &wgpu::Device) -> Page {
+        let vertices = Buffer::empty(
+            device,
+            wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            PAGE_VERTICES,
+        );
+        let infos = Buffer::new(
+            device,
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            &vec![SectionInfo::default(); PAGE_SECTIONS as usize],
+        );
+        let render_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Section Bind Group"),
+            layout: &self.render_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: infos.as_entire_binding(),
+            }],
+        });
+        let draws = self.culling.as_ref().map(|_| {
+            Buffer::empty(
+                device,
+                wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::INDIRECT,
+                PAGE_SECTIONS,
+            )
+        });
+        let cull_bind_group = self
+            .culling
+            .as_ref()
+            .zip(draws.as_ref())
+            .map(|(culling, draws)| {
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("Culling Bind Group"),
+                    layout: &culling.bind_group_layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: culling.frustum.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: infos.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: draws.as_entire_binding(),
+                        },
+                    ],
+                })
+            });
+        tracing::info!("Allocated terrain geometry page {}.", self.pages.len());
+        Page {
+            vertices,
+            allocator: RangeAllocator::new(PAGE_VERTICES),
+            infos,
+            sections: vec![SectionInfo::default(); PAGE_SECTIONS as usize],
+            free_slots: Vec::new(),
+            slot_count: 0,
+            draws,
+            render_bind_group,
+            cull_bind_group,
+        }
+    }
+}
+
+fn create_culling(device: &wgpu::Device) -> Culling {
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: None,
+        source: wgpu::ShaderSource::Wgsl(include_str!("../../../assets/shaders/cull.wgsl").into()),
+    });
+    let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("Culling Bind Group Layout"),
+        entries: &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            storage_entry(1, wgpu::ShaderStages::COMPUTE, true),
+            storage_entry(2, wgpu::ShaderStages::COMPUTE, false),
+        ],
+    });
+    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: None,
+        bind_group_layouts: &[&bind_group_layout],
+        push_constant_ranges: &[],
+    });
+    let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: Some("Terrain Culling Pipeline"),
+        layout: Some(&pipeline_layout),
+        module: &shader,
+        entry_point: "cs_main",
+    });
+    Culling {
+        pipeline,
+        bind_group_layout,
+        frustum: Buffer::new(
+            device,
+            wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            &[CullUniforms::default()],
+        ),
+    }
+}
+
+fn storage_entry(
+    binding: u32,
+    visibility: wgpu::ShaderStages,
+    read_only: bool,
+) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
@@ -1,87 +1,324 @@
 use common::{
-    chunk::Chunk,
+    block::BlockRegistry,
+    chunk::Section,
     math::{Vec2, Vec3},
+    world::ChunkNeighbourhood,
 };
 
 use super::{atlas::Atlas, Vertex};
 
-pub fn create_chunk_mesh(chunk: &Chunk, mesh: &mut Vec<Vertex>, pos: Vec2<i32>, atlas: &Atlas) {
-    for x in 0..Chunk::SIZE.x {
-        for y in 0..Chunk::SIZE.y {
-            for z in 0..Chunk::SIZE.z {
-                let origin = Vec3::new(x, y, z).as_::<i32>();
-                let block = chunk.get(origin).unwrap();
-                let offset = Vec3::new(
-                    pos.x as f32 * Chunk::SIZE.x as f32 + x as f32,
-                    y as f32,
-                    pos.y as f32 * Chunk::SIZE.z as f32 + z as f32,
-                );
-                let texture = atlas.block_texture(block);
-                // North
-                if Chunk::out_of_bounds(origin + Vec3::unit_z()) {
-                    let north = texture.values[0];
-                    mesh.push(Vertex::new(
-                        Vec3::unit_x() + Vec3::unit_y() + Vec3::unit_z() + offset,
-                        north,
-                    ));
-                    mesh.push(Vertex::new(Vec3::unit_x() + Vec3::unit_z() + offset, north));
-                    mesh.push(Vertex::new(Vec3::zero() + Vec3::unit_z() + offset, north));
-                    mesh.push(Vertex::new(Vec3::unit_y() + Vec3::unit_z() + offset, north));
-                }
+/// Algorithm used to turn chunks into meshes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MeshingMode {
+    /// One quad per visible block face.
+    Naive,
+    /// Coplanar faces with the same texture are merged into larger quads.
+    Greedy,
+}
 
-                // South
-                if Chunk::out_of_bounds(origin - Vec3::unit_z()) {
-                    let south = texture.values[1];
-                    mesh.push(Vertex::new(Vec3::unit_y() + offset, south));
-                    mesh.push(Vertex::new(Vec3::zero() + offset, south));
-                    mesh.push(Vertex::new(Vec3::unit_x() + offset, south));
-                    mesh.push(Vertex::new(Vec3::unit_x() + Vec3::unit_y() + offset, south));
-                }
+impl MeshingMode {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Naive => Self::Greedy,
+            Self::Greedy => Self::Naive,
+        }
+    }
+}
 
-                // East
-                if Chunk::out_of_bounds(origin + Vec3::unit_x()) {
-                    let east = texture.values[2];
-                    mesh.push(Vertex::new(Vec3::unit_x() + Vec3::unit_y() + offset, east));
-                    mesh.push(Vertex::new(Vec3::unit_x() + offset, east));
-                    mesh.push(Vertex::new(Vec3::unit_x() + Vec3::unit_z() + offset, east));
-                    mesh.push(Vertex::new(
-                        Vec3::unit_x() + Vec3::unit_z() + Vec3::unit_y() + offset,
-                        east,
-                    ));
-                }
-                // West
-                if Chunk::out_of_bounds(origin - Vec3::unit_x()) {
-                    let west = texture.values[3];
-                    mesh.push(Vertex::new(Vec3::unit_z() + Vec3::unit_y() + offset, west));
-                    mesh.push(Vertex::new(Vec3::unit_z() + offset, west));
-                    mesh.push(Vertex::new(Vec3::zero() + offset, west));
-                    mesh.push(Vertex::new(Vec3::unit_y() + offset, west));
+/// Describes how to build the quad of one of the sides of a block.
+struct Face {
+    /// Direction the face is looking at.
+    normal: Vec3<i32>,
+    /// Position of the first (top left) corner, relative to the block origin.
+    corner: Vec3<i32>,
+    /// Direction from the left to the right side of the quad.
+    u: Vec3<i32>,
+    /// Direction from the top to the bottom side of the quad.
+    v: Vec3<i32>,
+    /// Index of the face in [super::atlas::BlockTexture::values], also used as
+    /// the packed normal of its vertices.
+    index: u32,
+}
+
+// Quads are built counter-clockwise starting from the top left corner:
+// top left, bottom left, bottom right, top right.
+const FACES: [Face; 6] = [
+    // North
+    Face {
+        normal: Vec3::new(0, 0, 1),
+        corner: Vec3::new(1, 1, 1),
+        u: Vec3::new(-1, 0, 0),
+        v: Vec3::new(0, -1, 0),
+        index: 0,
+    },
+    // South
+    Face {
+        normal: Vec3::new(0, 0, -1),
+        corner: Vec3::new(0, 1, 0),
+        u: Vec3::new(1, 0, 0),
+        v: Vec3::new(0, -1, 0),
+        index: 1,
+    },
+    // East
+    Face {
+        normal: Vec3::new(1, 0, 0),
+        corner: Vec3::new(1, 1, 0),
+        u: Vec3::new(0, 0, 1),
+        v: Vec3::new(0, -1, 0),
+        index: 2,
+    },
+    // West
+    Face {
+        normal: Vec3::new(-1, 0, 0),
+        corner: Vec3::new(0, 1, 1),
+        u: Vec3::new(0, 0, -1),
+        v: Vec3::new(0, -1, 0),
+        index: 3,
+    },
+    // Top
+    Face {
+        normal: Vec3::new(0, 1, 0),
+        corner: Vec3::new(0, 1, 1),
+        u: Vec3::new(1, 0, 0),
+        v: Vec3::new(0, 0, -1),
+        index: 4,
+    },
+    // Bottom
+    Face {
+        normal: Vec3::new(0, -1, 0),
+        corner: Vec3::new(0, 0, 0),
+        u: Vec3::new(1, 0, 0),
+        v: Vec3::new(0, 0, 1),
+        index: 5,
+    },
+];
+
+/// Meshes the given section of the center chunk of the neighbourhood.
+///
+/// Vertices are positioned relative to the chunk origin.
+///
+/// Only the faces of non-air blocks that are not covered by an opaque block
+/// are emitted. Blocks across the section borders are looked up in the
+/// neighbouring sections and chunks; faces bordering a chunk that is not
+/// loaded are always emitted.
+pub fn create_section_mesh(
+    chunks: &ChunkNeighbourhood,
+    section: usize,
+    mesh: &mut Vec<Vertex>,
+    atlas: &Atlas,
+    blocks: &BlockRegistry,
+    mode: MeshingMode,
+) {
+    if chunks
+        .center()
+        .section(section)
+        .is_none_or(Section::is_empty)
+    {
+        return;
+    }
+    let min = Vec3::new(0, (section * Section::SIZE.y) as i32, 0);
+    match mode {
+        MeshingMode::Naive => create_naive_mesh(chunks, min, mesh, atlas, blocks),
+        MeshingMode::Greedy => create_greedy_mesh(chunks, min, mesh, atlas, blocks),
+    }
+}
+
+/// Meshes the section starting at `min`, one quad per visible face.
+fn create_naive_mesh(
+    chunks: &ChunkNeighbourhood,
+    min: Vec3<i32>,
+    mesh: &mut Vec<Vertex>,
+    atlas: &Atlas,
+    blocks: &BlockRegistry,
+) {
+    for x in 0..Section::SIZE.x {
+        for y in 0..Section::SIZE.y {
+            for z in 0..Section::SIZE.z {
+                let origin = min + Vec3::new(x, y, z).as_::<i32>();
+                for face in &FACES {
+                    if let Some(texture) = face_texture(chunks, origin, face, atlas, blocks) {
+                        let ao = face_ao(chunks, origin, face, blocks);
+                        push_quad(mesh, origin, face, Vec2::one(), texture, ao);
+                    }
                 }
+            }
+        }
+    }
+}
 
-                if Chunk::out_of_bounds(origin + Vec3::unit_y()) {
-                    // Top
-                    let top = texture.values[4];
-                    mesh.push(Vertex::new(Vec3::unit_z() + Vec3::unit_y() + offset, top));
-                    mesh.push(Vertex::new(Vec3::unit_y() + offset, top));
-                    mesh.push(Vertex::new(Vec3::unit_y() + Vec3::unit_x() + offset, top));
-                    mesh.push(Vertex::new(
-                        Vec3::unit_y() + Vec3::unit_x() + Vec3::unit_z() + offset,
-                        top,
-                    ));
+/// Meshes the section starting at `min`, merging visible faces into the
+/// largest rectangles it can find, one slice of the section at a time.
+///
+/// Only faces with the same texture and the same ambient occlusion are merged,
+/// so the result looks exactly like the naive mesh.
+fn create_greedy_mesh(
+    chunks: &ChunkNeighbourhood,
+    min: Vec3<i32>,
+    mesh: &mut Vec<Vertex>,
+    atlas: &Atlas,
+    blocks: &BlockRegistry,
+) {
+    let size = Section::SIZE.as_::<i32>();
+    for face in &FACES {
+        let (n, u, v) = (axis(face.normal), axis(face.u), axis(face.v));
+        let (width, height) = (size[u] as usize, size[v] as usize);
+
+        // The slice is walked along `u` and `v`, so it has to start at the
+        // opposite end when those point backwards.
+        let mut start = min;
+        start[u] += if face.u[u] > 0 { 0 } else { size[u] - 1 };
+        start[v] += if face.v[v] > 0 { 0 } else { size[v] - 1 };
+
+        let mut mask = vec![None; width * height];
+        for depth in 0..size[n] {
+            start[n] = min[n] + depth;
+            let block_at = |i: usize, j: usize| start + face.u * i as i32 + face.v * j as i32;
+
+            for j in 0..height {
+                for i in 0..width {
+                    let origin = block_at(i, j);
+                    mask[j * width + i] = face_texture(chunks, origin, face, atlas, blocks)
+                        .map(|texture| (texture, face_ao(chunks, origin, face, blocks)));
                 }
+            }
+
+            for j in 0..height {
+                let mut i = 0;
+                while i < width {
+                    let Some((texture, ao)) = mask[j * width + i] else {
+                        i += 1;
+                        continue;
+                    };
 
-                if Chunk::out_of_bounds(origin - Vec3::unit_y()) {
-                    // Bottom
-                    let bottom = texture.values[5];
-                    mesh.push(Vertex::new(Vec3::zero() + offset, bottom));
-                    mesh.push(Vertex::new(Vec3::unit_z() + offset, bottom));
-                    mesh.push(Vertex::new(
-                        Vec3::unit_z() + Vec3::unit_x() + offset,
-                        bottom,
-                    ));
-                    mesh.push(Vertex::new(Vec3::unit_x() + offset, bottom));
+                    let key = Some((texture, ao));
+                    let mut w = 1;
+                    while i + w < width && mask[j * width + i + w] == key {
+                        w += 1;
+                    }
+                    let mut h = 1;
+                    while j + h < height
+                        && mask[(j + h) * width + i..(j + h) * width + i + w]
+                            .iter()
+                            .all(|t| *t == key)
+                    {
+                        h += 1;
+                    }
+
+                    for row in j..j + h {
+                        mask[row * width + i..row * width + i + w].fill(None);
+                    }
+                    push_quad(
+                        mesh,
+                        block_at(i, j),
+                        face,
+                        Vec2::new(w, h).as_(),
+                        texture,
+                        ao,
+                    );
+                    i += w;
                 }
             }
         }
     }
 }
+
+/// Returns the texture of the given face of the block at `origin`, or `None`
+/// if the face is not visible.
+fn face_texture(
+    chunks: &ChunkNeighbourhood,
+    origin: Vec3<i32>,
+    face: &Face,
+    atlas: &Atlas,
+    blocks: &BlockRegistry,
+) -> Option<u32> {
+    let block = chunks.center().get(origin)?;
+    if block.is_air()
+        || chunks
+            .get(origin + face.normal)
+            .is_some_and(|block| blocks.is_opaque(block))
+    {
+        return None;
+    }
+    Some(atlas.block_texture(block).values[face.index as usize])
+}
+
+/// Computes the ambient occlusion of each corner of a face, in the same order
+/// as the quad vertices.
+///
+/// Each corner is darkened by the opaque blocks touching it in front of the
+/// face: 3 means fully lit and 0 fully occluded.
+fn face_ao(
+    chunks: &ChunkNeighbourhood,
+    origin: Vec3<i32>,
+    face: &Face,
+    blocks: &BlockRegistry,
+) -> [u32; 4] {
+    let front = origin + face.normal;
+    let solid =
+        |pos: Vec3<i32>| chunks.get(pos).is_some_and(|block| blocks.is_opaque(block)) as u32;
+    let corner_ao = |u: Vec3<i32>, v: Vec3<i32>| {
+        let (side1, side2, corner) = (solid(front + u), solid(front + v), solid(front + u + v));
+        if side1 == 1 && side2 == 1 {
+            0
+        } else {
+            3 - (side1 + side2 + corner)
+        }
+    };
+    [
+        corner_ao(-face.u, -face.v),
+        corner_ao(-face.u, face.v),
+        corner_ao(face.u, face.v),
+        corner_ao(face.u, -face.v),
+    ]
+}
+
+/// Pushes a quad covering `size` blocks, starting at the block at `origin`.
+///
+/// `origin` is relative to the chunk.
+fn push_quad(
+    mesh: &mut Vec<Vertex>,
+    origin: Vec3<i32>,
+    face: &Face,
+    size: Vec2<i32>,
+    texture: u32,
+    ao: [u32; 4],
+) {
+    let corner = origin + face.corner;
+    let (u, v) = (face.u * size.x, face.v * size.y);
+    let size = size.as_();
+    let vertex = |pos: Vec3<i32>, corner: u32| {
+        Vertex::new(
+            pos.as_(),
+            face.index,
+            corner,
+            texture,
+            size,
+            ao[corner as usize],
+        )
+    };
+    let vertices = [
+        vertex(corner, 0),
+        vertex(corner + v, 1),
+        vertex(corner + u + v, 2),
+        vertex(corner + u, 3),
+    ];
+    // Quads are split along the diagonal going through the first vertex. Use
+    // the other diagonal when it is brighter, otherwise the occlusion is
+    // interpolated unevenly across the two triangles.
+    if ao[0] + ao[2] < ao[1] + ao[3] {
+        mesh.extend_from_slice(&[vertices[1], vertices[2], vertices[3], vertices[0]]);
+    } else {
+        mesh.extend_from_slice(&vertices);
+    }
+}
+
+/// Index of the axis the given unit vector is aligned to.
+fn axis(dir: Vec3<i32>) -> usize {
+    if dir.x != 0 {
+        0
+    } else if dir.y != 0 {
+        1
+    } else {
+        2
+    }
+}
@@ -1,19 +1,33 @@
 pub mod atlas;
 pub mod buffer;
+pub mod geometry;
 pub mod mesh;
+pub mod outline;
 pub mod png_utils;
 pub mod texture;
 pub mod voxels;
 
 use std::sync::Arc;
 
-use common::math::{Mat4f, Vec3};
+use common::{
+    block::BlockRegistry,
+    chunk::Chunk,
+    math::{Mat4f, Vec2, Vec3},
+    thread_pool::ThreadPool,
+};
 use pollster::FutureExt;
 use wgpu::{CommandEncoderDescriptor, TextureViewDescriptor};
 use winit::window::Window;
 
 use crate::{
-    render::{atlas::Atlas, buffer::Buffer, texture::Texture, voxels::Voxels},
+    camera::Matrices,
+    render::{
+        atlas::Atlas,
+        buffer::Buffer,
+        outline::Outline,
+        texture::Texture,
+        voxels::{DrawStats, Voxels},
+    },
     scene::Scene,
 };
 
@@ -22,9 +36,11 @@ use crate::{
 pub struct Uniforms {
     proj: [[f32; 4]; 4],
     view: [[f32; 4]; 4],
+    /// See [`Matrices::origin`].
+    origin: [i32; 3],
     atlas_size: u32,
     atlas_tile_count: u32,
-    _padding: [f32; 2],
+    _padding: [u32; 3],
 }
 
 impl Default for Uniforms {
@@ -32,43 +48,77 @@ impl Default for Uniforms {
         Self {
             proj: Mat4f::identity().into_col_arrays(),
             view: Mat4f::identity().into_col_arrays(),
+            origin: [0; 3],
             atlas_size: 0,
             atlas_tile_count: 0,
-            _padding: [0.0; 2],
+            _padding: [0; 3],
         }
     }
 }
 
 impl Uniforms {
-    pub fn new(proj: Mat4f, view: Mat4f, atlas_size: u32, atlas_tile_count: u32) -> Self {
+    pub fn new(matrices: &Matrices, atlas_size: u32, atlas_tile_count: u32) -> Self {
         Self {
-            proj: proj.into_col_arrays(),
-            view: view.into_col_arrays(),
+            proj: matrices.proj.into_col_arrays(),
+            view: matrices.view.into_col_arrays(),
+            origin: matrices.origin.into_array(),
             atlas_size,
             atlas_tile_count,
-            _padding: [0.0; 2],
+            _padding: [0; 3],
         }
     }
 }
 
+/// Terrain vertex, packed in two words.
+///
+/// Positions are relative to the chunk the vertex belongs to, the chunk
+/// offset is supplied separately when drawing. The layout is:
+///
+/// - `data[0]`: position x (5 bits), y (9 bits), z (5 bits), normal (3 bits),
+///   corner (2 bits) and ambient occlusion (2 bits), starting from the least
+///   significant bit.
+/// - `data[1]`: texture id (16 bits), quad width - 1 (8 bits) and
+///   quad height - 1 (8 bits).
+///
+/// Must be kept in sync with `unpack_vertex` in `voxels.wgsl`.
 #[repr(C)]
 #[derive(bytemuck::Pod, bytemuck::Zeroable, Clone, Copy)]
 pub struct Vertex {
-    pos: [f32; 3],
-    texture_id: u32,
+    data: [u32; 2],
 }
 
 impl Vertex {
-    pub fn new(v: Vec3<f32>, texture_id: u32) -> Self {
+    /// Creates a vertex of a quad.
+    ///
+    /// - `pos`: position within the chunk, at most [Chunk::SIZE] on each axis.
+    /// - `normal`: index of the face the quad belongs to.
+    /// - `corner`: which corner of the quad this is, counter-clockwise from the
+    ///   top left one.
+    /// - `size`: dimensions of the quad, in blocks, to tile its texture.
+    /// - `ao`: ambient occlusion, from 0 (fully occluded) to 3 (fully lit).
+    pub fn new(
+        pos: Vec3<u32>,
+        normal: u32,
+        corner: u32,
+        texture_id: u32,
+        size: Vec2<u32>,
+        ao: u32,
+    ) -> Self {
+        debug_assert!(pos.x <= Chunk::SIZE.x as u32);
+        debug_assert!(pos.y <= Chunk::SIZE.y as u32);
+        debug_assert!(pos.z <= Chunk::SIZE.z as u32);
+        debug_assert!((1..=256).contains(&size.x) && (1..=256).contains(&size.y));
         Self {
-            pos: v.into_array(),
-            texture_id,
+            data: [
+                pos.x | pos.y << 5 | pos.z << 14 | normal << 19 | corner << 22 | ao << 24,
+                texture_id & 0xffff | (size.x - 1) << 16 | (size.y - 1) << 24,
+            ],
         }
     }
 
     pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
         const ATTRS: [wgpu::VertexAttribute; 2] =
-            wgpu::vertex_attr_array![0 => Float32x3, 1 => Uint32];
+            wgpu::vertex_attr_array![0 => Uint32, 1 => Uint32];
         wgpu::VertexBufferLayout {
             step_mode: wgpu::VertexStepMode::Vertex,
             attributes: &ATTRS,
@@ -92,16 +142,18 @@ pub struct Renderer {
     /// Common Bind Groups
     common_bg: wgpu::BindGroup,
     /// Block texture atlas.
-    atlas: Atlas,
+    atlas: Arc<Atlas>,
     /// Terrain Depth Texture
     depth_texture: Texture,
     /// Voxel Renderer
     voxels: Voxels,
+    /// Outline of the targeted block
+    outline: Outline,
 }
 
 impl Renderer {
     #[allow(clippy::vec_init_then_push)]
-    pub fn new(platform: &Arc<Window>) -> Self {
+    pub fn new(platform: &Arc<Window>, pool: Arc<ThreadPool>, blocks: Arc<BlockRegistry>) -> Self {
         let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
         let surface = instance.create_surface(platform.clone()).unwrap();
 
@@ -113,10 +165,27 @@ impl Renderer {
             .block_on()
             .unwrap();
 
+        // Terrain is drawn indirectly when possible, see [geometry].
+        let indirect_features =
+            wgpu::Features::MULTI_DRAW_INDIRECT | wgpu::Features::INDIRECT_FIRST_INSTANCE;
+        let indirect = adapter.features().contains(indirect_features);
         let (device, queue) = adapter
-            .request_device(&wgpu::DeviceDescriptor::default(), None)
+            .request_device(
+                &wgpu::DeviceDescriptor {
+                    required_features: if indirect {
+                        indirect_features
+                    } else {
+                        wgpu::Features::empty()
+                    },
+                    ..Default::default()
+                },
+                None,
+            )
             .block_on()
             .unwrap();
+        if !indirect {
+            tracing::warn!("Indirect drawing is not supported, terrain is culled on the CPU.");
+        }
 
         let (width, height) = platform.inner_size().into();
         let config = surface.get_default_config(&adapter, width, height).unwrap();
@@ -127,7 +196,7 @@ impl Renderer {
             wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
             &[Uniforms::default()],
         );
-        let atlas = Atlas::pack_textures("assets/textures/block/").unwrap();
+        let atlas = Arc::new(Atlas::pack_textures("assets/textures/block/", &blocks).unwrap());
         let atlas_texture = Texture::new(&device, &queue, &atlas.image);
         let depth_texture = Texture::depth(&device, config.width, config.height);
         let common_bind_group_layout =
@@ -136,7 +205,7 @@ impl Renderer {
                 entries: &[
                     wgpu::BindGroupLayoutEntry {
                         binding: 0,
-                        visibility: wgpu::ShaderStages::VERTEX,
+                        visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
                         ty: wgpu::BindingType::Buffer {
                             ty: wgpu::BufferBindingType::Uniform,
                             has_dynamic_offset: false,
@@ -181,7 +250,15 @@ impl Renderer {
             ],
         });
 
-        let voxels = Voxels::new(&device, &common_bind_group_layout, &config, &atlas);
+        let voxels = Voxels::new(
+            &device,
+            &common_bind_group_layout,
+            &config,
+            pool,
+            blocks,
+            indirect,
+        );
+        let outline = Outline::new(&device, &common_bind_group_layout, &config);
         tracing::info!("Renderer initialized.");
 
         Self {
@@ -194,6 +271,7 @@ impl Renderer {
             atlas,
             depth_texture,
             voxels,
+            outline,
         }
     }
 
@@ -204,13 +282,29 @@ impl Renderer {
         self.depth_texture = Texture::depth(&self.device, width, height);
     }
 
+    /// Switches to the next [mesh::MeshingMode], re-meshing every loaded chunk.
+    pub fn cycle_meshing_mode(&mut self) {
+        let mode = self.voxels.meshing_mode().next();
+        tracing::info!("Meshing mode set to {:?}.", mode);
+        self.voxels.set_meshing_mode(mode);
+    }
+
+    /// Statistics about the terrain drawn in the last frame.
+    pub fn draw_stats(&self) -> DrawStats {
+        self.voxels.stats()
+    }
+
     pub fn render(&mut self, scene: &mut Scene) {
+        self.voxels
+            .update(&self.device, &self.queue, scene.terrain_mut(), &self.atlas);
+        self.outline
+            .update(&self.queue, scene.target().map(|target| target.pos));
+
         let matrices = scene.camera_matrices();
         self.uniforms_buffer.write(
             &self.queue,
             &[Uniforms::new(
-                matrices.proj,
-                matrices.view,
+                &matrices,
                 self.atlas.image.width,
                 self.atlas.tile_size as u32,
             )],
@@ -221,6 +315,9 @@ impl Renderer {
         let mut encoder = self
             .device
             .create_command_encoder(&CommandEncoderDescriptor::default());
+        let frustum = matrices.frustum();
+        self.voxels
+            .cull(&mut encoder, &self.queue, &frustum, matrices.origin);
 
         {
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
@@ -250,7 +347,9 @@ impl Renderer {
                 occlusion_query_set: None,
             });
 
-            self.voxels.draw(&mut render_pass, &self.common_bg);
+            self.voxels
+                .draw(&mut render_pass, &self.common_bg, &frustum, matrices.origin);
+            self.outline.draw(&mut render_pass, &self.common_bg);
         }
 
         self.queue.submit(std::iter::once(encoder.finish()));
@@ -0,0 +1,175 @@
+use common::math::Vec3;
+
+use super::{buffer::Buffer, texture::Texture};
+
+/// How far the outline is drawn outside the block, so its edges are not
+/// hidden by the faces of the block.
+const INFLATE: f32 = 0.002;
+
+/// Per-outline data available on the GPU.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct OutlineUniforms {
+    /// World position of the outlined block.
+    offset: [i32; 3],
+    _padding: u32,
+}
+
+/// Draws the edges of the block targeted by the player.
+pub struct Outline {
+    render_pipeline: wgpu::RenderPipeline,
+    /// The 12 edges of a block, as pairs of vertices.
+    vertices: Buffer<[f32; 3]>,
+    uniforms: Buffer<OutlineUniforms>,
+    bind_group: wgpu::BindGroup,
+    /// The outlined block, if any.
+    target: Option<Vec3<i32>>,
+}
+
+impl Outline {
+    pub fn new(
+        device: &wgpu::Device,
+        common_bg_layout: &wgpu::BindGroupLayout,
+        config: &wgpu::SurfaceConfiguration,
+    ) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: None,
+            source: wgpu::ShaderSource::Wgsl(
+                include_str!("../../../assets/shaders/outline.wgsl").into(),
+            ),
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Outline Bind Group Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let uniforms = Buffer::new(
+            device,
+            wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            &[OutlineUniforms {
+                offset: [0; 3],
+                _padding: 0,
+            }],
+        );
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Outline Bind Group"),
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniforms.as_entire_binding(),
+            }],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[common_bg_layout, &bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        const ATTRS: [wgpu::VertexAttribute; 1] = wgpu::vertex_attr_array![0 => Float32x3];
+        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Outline Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[wgpu::VertexBufferLayout {
+                    step_mode: wgpu::VertexStepMode::Vertex,
+                    attributes: &ATTRS,
+                    array_stride: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                }],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: config.format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::all(),
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::LineList,
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Texture::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        Self {
+            render_pipeline,
+            vertices: Buffer::new(device, wgpu::BufferUsages::VERTEX, &cube_edges()),
+            uniforms,
+            bind_group,
+            target: None,
+        }
+    }
+
+    /// Moves the outline to the given block, or hides it.
+    pub fn update(&mut self, queue: &wgpu::Queue, target: Option<Vec3<i32>>) {
+        if target == self.target {
+            return;
+        }
+        self.target = target;
+        if let Some(pos) = target {
+            self.uniforms.write(
+                queue,
+                &[OutlineUniforms {
+                    offset: pos.into_array(),
+                    _padding: 0,
+                }],
+            );
+        }
+    }
+
+    pub fn draw<'a>(&'a self, frame: &mut wgpu::RenderPass<'a>, common_bg: &'a wgpu::BindGroup) {
+        if self.target.is_none() {
+            return;
+        }
+        frame.set_pipeline(&self.render_pipeline);
+        frame.set_bind_group(0, common_bg, &[]);
+        frame.set_bind_group(1, &self.bind_group, &[]);
+        frame.set_vertex_buffer(0, self.vertices.slice());
+        frame.draw(0..self.vertices.len(), 0..1);
+    }
+}
+
+/// Returns the edges of a unit cube, slightly inflated, as pairs of vertices.
+fn cube_edges() -> Vec<[f32; 3]> {
+    let (min, max) = (-INFLATE, 1.0 + INFLATE);
+    let corner = |i: usize| {
+        [
+            if i & 1 == 0 { min } else { max },
+            if i & 2 == 0 { min } else { max },
+            if i & 4 == 0 { min } else { max },
+        ]
+    };
+    let mut vertices = Vec::with_capacity(24);
+    for i in 0..8 {
+        // Connect each corner to the corners differing on one axis only,
+        // so every edge is added once.
+        for axis in [1, 2, 4] {
+            if i & axis == 0 {
+                vertices.push(corner(i));
+                vertices.push(corner(i | axis));
+            }
+        }
+    }
+    vertices
+}
@@ -1,14 +1,85 @@
-use common::{chunk::Chunk, math::Vec2};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{mpsc, Arc},
+};
 
-use super::{atlas::Atlas, buffer::Buffer, mesh, texture::Texture, Vertex};
+use common::{
+    block::BlockRegistry,
+    chunk::Chunk,
+    math::{Vec2, Vec3},
+    thread_pool::ThreadPool,
+    world::World,
+};
+
+use crate::{camera::Frustum, terrain::Terrain};
+
+use super::{
+    atlas::Atlas,
+    buffer::Buffer,
+    geometry::{GeometryPool, SectionHandle},
+    mesh::{self, MeshingMode},
+    texture::Texture,
+    Vertex,
+};
+
+/// Maximum number of section meshes uploaded to the GPU in a single frame.
+const MAX_UPLOADS_PER_FRAME: usize = 8;
+
+/// Number of sections drawn in the last frame, for diagnostics.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DrawStats {
+    /// Sections drawn.
+    pub drawn: usize,
+    /// Sections skipped because they were outside of the view.
+    pub culled: usize,
+    /// Whether sections were culled on the GPU, in which case every section
+    /// counts as drawn.
+    pub gpu_culling: bool,
+}
+
+/// Identifies a section by the position of its chunk and its index.
+type SectionPos = (Vec2<i32>, usize);
+
+/// The geometry of each section of a chunk, from the bottom to the top.
+/// Sections without any visible face have none.
+type ChunkMesh = [Option<SectionHandle>; Chunk::SECTIONS];
+
+/// A section mesh built by a worker thread.
+struct MeshResult {
+    pos: SectionPos,
+    /// Identifies the job that built this mesh, so outdated results can be
+    /// discarded.
+    job: u64,
+    vertices: Vec<Vertex>,
+}
 
 pub struct Voxels {
     /// Terrain render pipeline
     render_pipeline: wgpu::RenderPipeline,
-    // /// Terrain geometry
-    chunk_meshes: Vec<Buffer<Vertex>>,
+    /// Terrain geometry
+    geometry: GeometryPool,
+    /// Where the geometry of each chunk is, by chunk position
+    chunk_meshes: HashMap<Vec2<i32>, ChunkMesh>,
     /// Terrain indices
+    ///
+    /// Shared by every section mesh, so it is grown to fit the largest one.
     index_buffer: Buffer<u32>,
+    /// Workers used for meshing.
+    pool: Arc<ThreadPool>,
+    /// Block definitions used for meshing.
+    blocks: Arc<BlockRegistry>,
+    /// Sections that have to be re-meshed.
+    dirty: HashSet<SectionPos>,
+    /// Latest meshing job queued for each section.
+    pending_meshes: HashMap<SectionPos, u64>,
+    next_job: u64,
+    mesh_tx: mpsc::Sender<MeshResult>,
+    mesh_rx: mpsc::Receiver<MeshResult>,
+    /// Algorithm used for new meshes.
+    meshing_mode: MeshingMode,
+    /// Whether every loaded chunk has to be re-meshed on the next update.
+    remesh_all: bool,
+    stats: DrawStats,
 }
 
 impl Voxels {
@@ -16,7 +87,9 @@ impl Voxels {
         device: &wgpu::Device,
         common_bg_layout: &wgpu::BindGroupLayout,
         config: &wgpu::SurfaceConfiguration,
-        atlas: &Atlas,
+        pool: Arc<ThreadPool>,
+        blocks: Arc<BlockRegistry>,
+        indirect: bool,
     ) -> Self {
         let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: None,
@@ -25,9 +98,11 @@ impl Voxels {
             ),
         });
 
+        let geometry = GeometryPool::new(device, indirect);
+
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: None,
-            bind_group_layouts: &[&common_bg_layout],
+            bind_group_layouts: &[common_bg_layout, geometry.render_bind_group_layout()],
             push_constant_ranges: &[],
         });
 
@@ -72,49 +147,238 @@ impl Voxels {
             multiview: None,
         });
 
-        // Test geometry
-        let mut chunk_generation = vec![];
-        for x in 0..3 {
-            for z in 0..3 {
-                chunk_generation.push((Vec2::new(x, z), Chunk::flat()));
+        let index_buffer =
+            Buffer::new(device, wgpu::BufferUsages::INDEX, &compute_voxel_indices(0));
+
+        let (mesh_tx, mesh_rx) = mpsc::channel();
+
+        Self {
+            render_pipeline,
+            geometry,
+            chunk_meshes: HashMap::new(),
+            index_buffer,
+            pool,
+            blocks,
+            dirty: HashSet::new(),
+            pending_meshes: HashMap::new(),
+            next_job: 0,
+            mesh_tx,
+            mesh_rx,
+            meshing_mode: MeshingMode::Greedy,
+            remesh_all: false,
+            stats: DrawStats::default(),
+        }
+    }
+
+    /// Updates the chunk meshes with the chunks loaded or unloaded by the
+    /// [Terrain] since the last update.
+    ///
+    /// Meshing happens on the worker threads; only the upload of finished
+    /// meshes is done here, and at most [MAX_UPLOADS_PER_FRAME] per call.
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        terrain: &mut Terrain,
+        atlas: &Arc<Atlas>,
+    ) {
+        for pos in terrain.drain_unloaded().collect::<Vec<_>>() {
+            for handle in self
+                .chunk_meshes
+                .remove(&pos)
+                .into_iter()
+                .flatten()
+                .flatten()
+            {
+                self.geometry.remove(queue, handle);
+            }
+            self.dirty.retain(|(chunk, _)| *chunk != pos);
+            self.pending_meshes.retain(|(chunk, _), _| *chunk != pos);
+            // The border faces of the chunks around it were hidden by its
+            // blocks, and are now exposed.
+            for x in -1..=1 {
+                for z in -1..=1 {
+                    if x != 0 || z != 0 {
+                        self.mark_chunk_dirty(pos + Vec2::new(x, z));
+                    }
+                }
             }
         }
 
-        let mut chunk_meshes = vec![];
-        let mut vertex_count = 0;
+        if std::mem::take(&mut self.remesh_all) {
+            for (pos, _) in terrain.world().chunks() {
+                self.mark_chunk_dirty(pos);
+            }
+        }
+        // Chunks around a newly loaded one need to be re-meshed as well, as
+        // their border faces may now be hidden or occluded.
+        for pos in terrain.drain_loaded().collect::<Vec<_>>() {
+            for x in -1..=1 {
+                for z in -1..=1 {
+                    self.mark_chunk_dirty(pos + Vec2::new(x, z));
+                }
+            }
+        }
+        // Blocks affect the faces and ambient occlusion of the blocks around
+        // them, which may belong to a neighbouring section.
+        for pos in terrain.drain_changed().collect::<Vec<_>>() {
+            for x in -1..=1 {
+                for y in -1..=1 {
+                    for z in -1..=1 {
+                        self.mark_block_dirty(pos + Vec3::new(x, y, z));
+                    }
+                }
+            }
+        }
+        self.queue_dirty(queue, terrain, atlas);
 
-        for (pos, chunk) in chunk_generation {
-            let mut chunk_mesh = vec![];
-            mesh::create_chunk_mesh(&chunk, &mut chunk_mesh, pos, atlas);
-            chunk_meshes.push(Buffer::new(device, wgpu::BufferUsages::VERTEX, &chunk_mesh));
-            vertex_count += chunk_mesh.len() as u32;
+        let mut uploads = 0;
+        while uploads < MAX_UPLOADS_PER_FRAME {
+            let Ok(result) = self.mesh_rx.try_recv() else {
+                break;
+            };
+            if self.pending_meshes.get(&result.pos) != Some(&result.job) {
+                // The chunk was unloaded or re-meshed in the meantime.
+                continue;
+            }
+            self.pending_meshes.remove(&result.pos);
+            let (pos, section) = result.pos;
+            self.reserve_indices(device, result.vertices.len());
+            let chunk_mesh = self.chunk_meshes.entry(pos).or_default();
+            if let Some(handle) = chunk_mesh[section].take() {
+                self.geometry.remove(queue, handle);
+            }
+            if !result.vertices.is_empty() {
+                let origin = World::block_pos(pos, Vec3::zero());
+                chunk_mesh[section] =
+                    Some(
+                        self.geometry
+                            .insert(device, queue, origin, section, &result.vertices),
+                    );
+            }
+            uploads += 1;
         }
+    }
 
-        let index_buffer = Buffer::new(
-            device,
-            wgpu::BufferUsages::INDEX,
-            &compute_voxel_indices(vertex_count as usize),
-        );
+    pub fn meshing_mode(&self) -> MeshingMode {
+        self.meshing_mode
+    }
 
-        Self {
-            render_pipeline,
-            chunk_meshes,
-            index_buffer,
+    /// Changes the meshing algorithm and re-meshes every loaded chunk with it.
+    pub fn set_meshing_mode(&mut self, mode: MeshingMode) {
+        self.meshing_mode = mode;
+        self.remesh_all = true;
+    }
+
+    /// Marks every section of the given chunk to be re-meshed.
+    pub fn mark_chunk_dirty(&mut self, pos: Vec2<i32>) {
+        self.dirty
+            .extend((0..Chunk::SECTIONS).map(|section| (pos, section)));
+    }
+
+    /// Marks the section containing the given block to be re-meshed.
+    pub fn mark_block_dirty(&mut self, pos: Vec3<i32>) {
+        let (chunk_pos, local) = World::split_pos(pos);
+        if let Some(section) = Chunk::section_index(local.y) {
+            self.dirty.insert((chunk_pos, section));
         }
     }
 
+    /// Queues a meshing job for every dirty section.
+    ///
+    /// Sections that are not loaded are dropped, and empty ones have their
+    /// geometry removed right away.
+    fn queue_dirty(&mut self, queue: &wgpu::Queue, terrain: &Terrain, atlas: &Arc<Atlas>) {
+        let mut neighbourhoods = HashMap::new();
+        for (pos, section) in std::mem::take(&mut self.dirty) {
+            let Some(chunks) = neighbourhoods
+                .entry(pos)
+                .or_insert_with(|| terrain.world().neighbourhood(pos))
+            else {
+                continue;
+            };
+            if chunks.center().sections()[section].is_empty() {
+                self.pending_meshes.remove(&(pos, section));
+                let handle = self
+                    .chunk_meshes
+                    .get_mut(&pos)
+                    .and_then(|chunk_mesh| chunk_mesh[section].take());
+                if let Some(handle) = handle {
+                    self.geometry.remove(queue, handle);
+                }
+                continue;
+            }
+
+            let job = self.next_job;
+            self.next_job += 1;
+            self.pending_meshes.insert((pos, section), job);
+
+            let chunks = chunks.clone();
+            let atlas = Arc::clone(atlas);
+            let mesh_tx = self.mesh_tx.clone();
+            let blocks = Arc::clone(&self.blocks);
+            let mode = self.meshing_mode;
+            self.pool.execute(move || {
+                let mut vertices = vec![];
+                mesh::create_section_mesh(&chunks, section, &mut vertices, &atlas, &blocks, mode);
+                let _ = mesh_tx.send(MeshResult {
+                    pos: (pos, section),
+                    job,
+                    vertices,
+                });
+            });
+        }
+    }
+
+    /// Makes sure the index buffer can index a mesh with the given number of
+    /// vertices.
+    fn reserve_indices(&mut self, device: &wgpu::Device, vertex_count: usize) {
+        let index_count = vertex_count / 4 * 6;
+        if index_count > self.index_buffer.len() as usize {
+            self.index_buffer = Buffer::new(
+                device,
+                wgpu::BufferUsages::INDEX,
+                &compute_voxel_indices(vertex_count),
+            );
+        }
+    }
+
+    /// Statistics about the last call to [`Voxels::draw`].
+    pub fn stats(&self) -> DrawStats {
+        self.stats
+    }
+
+    /// Culls the sections on the GPU, if they are drawn indirectly. Must be
+    /// recorded before the render pass drawing them.
+    ///
+    /// The frustum is relative to `origin`, see
+    /// [`Matrices::origin`](crate::camera::Matrices::origin).
+    pub fn cull(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        queue: &wgpu::Queue,
+        frustum: &Frustum,
+        origin: Vec3<i32>,
+    ) {
+        self.geometry.cull(encoder, queue, frustum, origin);
+    }
+
+    /// Draws the sections that intersect the frustum.
     pub fn draw<'a>(
         &'a mut self,
         frame: &mut wgpu::RenderPass<'a>,
         common_bg: &'a wgpu::BindGroup,
+        frustum: &Frustum,
+        origin: Vec3<i32>,
     ) {
+        self.stats = DrawStats::default();
+        if self.chunk_meshes.is_empty() {
+            return;
+        }
         frame.set_pipeline(&self.render_pipeline);
         frame.set_bind_group(0, common_bg, &[]);
         frame.set_index_buffer(self.index_buffer.slice(), wgpu::IndexFormat::Uint32);
-        for chunk_mesh in &self.chunk_meshes {
-            frame.set_vertex_buffer(0, chunk_mesh.slice());
-            frame.draw_indexed(0..chunk_mesh.len() / 4 * 6, 0, 0..1);
-        }
+        self.geometry.draw(frame, frustum, origin, &mut self.stats);
     }
 }
 
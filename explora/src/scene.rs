@@ -1,20 +1,50 @@
-use common::math::Vec3;
+use std::sync::Arc;
 
-use crate::camera::{Camera, Matrices};
+use common::{
+    block::{BlockId, BlockRegistry},
+    math::{Vec2, Vec3},
+    raycast::{self, RaycastHit},
+    world::World,
+};
+
+use crate::{
+    camera::{Camera, Matrices},
+    client::Client,
+    player::{MovementMode, Player, EYE_HEIGHT},
+    terrain::Terrain,
+};
 
 pub struct Scene {
     camera: Camera,
+    player: Player,
     movement_dir: Vec3<f32>,
+    blocks: Arc<BlockRegistry>,
+    terrain: Terrain,
+    /// The server the world comes from.
+    client: Client,
+    /// The block the camera looks at, if any is in reach.
+    target: Option<RaycastHit>,
+    /// The block placed by [`Scene::place_block`].
+    selected_block: BlockId,
 }
 
-// TODO: make this configurable
-const FLY_CAMERA_SPEED: f32 = 7.0;
+/// Distance, in blocks, at which the player can reach blocks.
+const REACH: f32 = 6.0;
 
 impl Scene {
-    pub fn new(aspect: f32) -> Self {
+    /// Creates a scene showing the world hosted by the server `client` is
+    /// connected to.
+    pub fn new(aspect: f32, client: Client, blocks: Arc<BlockRegistry>) -> Self {
+        let camera = Camera::new(aspect);
         Self {
             movement_dir: Vec3::zero(),
-            camera: Camera::new(aspect),
+            player: Player::new(camera.pos() - Vec3::unit_y() * EYE_HEIGHT),
+            camera,
+            blocks,
+            terrain: Terrain::new(),
+            client,
+            target: None,
+            selected_block: BlockId::from_raw(1),
         }
     }
 
@@ -29,14 +59,103 @@ impl Scene {
         self.camera.set_aspect_ratio(w / h);
     }
 
+    /// Switches the player between walking and flying through blocks.
+    pub fn toggle_movement_mode(&mut self) {
+        self.player.toggle_mode();
+        tracing::info!("Movement mode set to {:?}.", self.player.mode());
+    }
+
+    pub fn movement_mode(&self) -> MovementMode {
+        self.player.mode()
+    }
+
     pub fn tick(&mut self, dt: f32) {
-        let dx = self.movement_dir.x * FLY_CAMERA_SPEED * dt;
-        let dy = self.movement_dir.y * FLY_CAMERA_SPEED * dt;
-        let dz = self.movement_dir.z * FLY_CAMERA_SPEED * dt;
-        self.camera.move_by(dx, dy, dz);
+        let horizontal = self.camera.forward_xz() * self.movement_dir.z
+            - self.camera.right() * self.movement_dir.x;
+        let world = self.terrain.world();
+        let blocks = &self.blocks;
+        self.player.tick(
+            dt,
+            Vec2::new(horizontal.x, horizontal.z),
+            self.movement_dir.y,
+            // Chunks that are not loaded yet are solid, so the player does
+            // not fall through them.
+            |pos| {
+                if world.contains_chunk(World::chunk_pos(pos)) {
+                    world
+                        .get_block(pos)
+                        .is_some_and(|block| blocks.is_solid(block))
+                } else {
+                    true
+                }
+            },
+        );
+        self.camera.set_pos(self.player.eye_pos());
+        if let Some(pos) = self.client.tick(&mut self.terrain, self.camera.pos()) {
+            self.player.set_pos(pos - Vec3::unit_y() * EYE_HEIGHT);
+            self.camera.set_pos(pos);
+        }
+        let world = self.terrain.world();
+        self.target = raycast::raycast(self.camera.pos(), self.camera.forward(), REACH, |pos| {
+            world.get_block(pos).is_some_and(|block| !block.is_air())
+        });
+    }
+
+    /// The block the camera looks at and the face it sees, if any is in
+    /// reach.
+    pub fn target(&self) -> Option<RaycastHit> {
+        self.target
+    }
+
+    pub fn selected_block(&self) -> BlockId {
+        self.selected_block
+    }
+
+    pub fn select_block(&mut self, block: BlockId) {
+        self.selected_block = block;
+    }
+
+    /// Breaks the targeted block.
+    pub fn break_block(&mut self) {
+        if let Some(target) = self.target.take() {
+            self.client
+                .set_block(&mut self.terrain, target.pos, BlockId::AIR);
+        }
+    }
+
+    /// Places the selected block against the targeted face.
+    pub fn place_block(&mut self) {
+        let Some(target) = self.target else {
+            return;
+        };
+        let pos = target.pos + target.normal;
+        if target.normal == Vec3::zero()
+            || (self.player.mode() == MovementMode::Walk
+                && self.player.aabb().intersects_block(pos))
+        {
+            return;
+        }
+        if self.terrain.world().get_block(pos) == Some(BlockId::AIR) {
+            self.client
+                .set_block(&mut self.terrain, pos, self.selected_block);
+        }
+    }
+
+    /// Why the connection to the server was closed, if it was.
+    pub fn disconnect_reason(&self) -> Option<&str> {
+        self.client.disconnect_reason()
     }
 
     pub fn camera_matrices(&mut self) -> Matrices {
         self.camera.compute_matrices()
     }
+
+    /// Leaves the server.
+    pub fn quit(&mut self) {
+        self.client.disconnect("Quit");
+    }
+
+    pub fn terrain_mut(&mut self) -> &mut Terrain {
+        &mut self.terrain
+    }
 }
@@ -0,0 +1,51 @@
+use std::sync::Arc;
+
+use common::{
+    block::BlockRegistry, net::ChannelConnection, save::WorldSave, terrain::HeightmapGenerator,
+};
+use server::{Server, ServerConfig, ServerHandle, WORLD_DIR, WORLD_SEED};
+
+use crate::client::{Client, ClientError};
+
+/// A server running in the background of the game, hosting a local world
+/// for a single player.
+pub struct Singleplayer {
+    /// `None` once the server is stopped.
+    server: Option<ServerHandle>,
+}
+
+impl Singleplayer {
+    /// Starts the server with the given blocks and joins it with the given
+    /// player name.
+    pub fn start(name: &str, blocks: &BlockRegistry) -> Result<(Self, Client), ClientError> {
+        let (save, world_blocks) = WorldSave::open(WORLD_DIR, WORLD_SEED, blocks)?;
+        let generator = HeightmapGenerator::new(save.seed(), &world_blocks);
+        let mut server = Server::new(
+            ServerConfig::default(),
+            Arc::new(world_blocks),
+            Arc::new(generator),
+            Some(Arc::new(save)),
+        );
+
+        let (server_end, client_end) = ChannelConnection::pair(["server", "local"]);
+        server.network_mut().add_connection(Box::new(server_end));
+        let singleplayer = Self {
+            server: Some(server.start()),
+        };
+        let client = Client::new(Box::new(client_end), name, blocks)?;
+        Ok((singleplayer, client))
+    }
+
+    /// Stops the server, saving the world.
+    pub fn stop(&mut self) {
+        if let Some(server) = self.server.take() {
+            server.stop();
+        }
+    }
+}
+
+impl Drop for Singleplayer {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
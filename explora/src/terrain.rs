@@ -0,0 +1,76 @@
+use std::sync::Arc;
+
+use common::{
+    block::BlockId,
+    chunk::Chunk,
+    math::{Vec2, Vec3},
+    world::World,
+};
+
+/// The chunks of the world sent by the server.
+///
+/// Changes are queued so the renderer can pick them up and update its
+/// meshes.
+#[derive(Default)]
+pub struct Terrain {
+    world: World,
+    /// Chunks loaded since the last call to [`Terrain::drain_loaded`].
+    loaded: Vec<Vec2<i32>>,
+    /// Chunks unloaded since the last call to [`Terrain::drain_unloaded`].
+    unloaded: Vec<Vec2<i32>>,
+    /// Blocks changed since the last call to [`Terrain::drain_changed`].
+    changed: Vec<Vec3<i32>>,
+}
+
+impl Terrain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn world(&self) -> &World {
+        &self.world
+    }
+
+    /// Adds or replaces a chunk received from the server.
+    pub fn insert_chunk(&mut self, pos: Vec2<i32>, chunk: Arc<Chunk>) {
+        self.world.insert_chunk(pos, chunk);
+        self.loaded.push(pos);
+    }
+
+    /// Removes a chunk the server stopped sending updates for.
+    pub fn remove_chunk(&mut self, pos: Vec2<i32>) {
+        if self.world.remove_chunk(pos).is_some() {
+            self.unloaded.push(pos);
+        }
+    }
+
+    /// Sets the block at the given world position, returning the previous one.
+    ///
+    /// See [`World::set_block`].
+    pub fn set_block(&mut self, pos: Vec3<i32>, block: BlockId) -> Option<BlockId> {
+        let previous = self.world.set_block(pos, block)?;
+        if previous != block {
+            self.changed.push(pos);
+        }
+        Some(previous)
+    }
+
+    /// Takes the positions of the chunks loaded since the last call.
+    pub fn drain_loaded(&mut self) -> impl Iterator<Item = Vec2<i32>> + '_ {
+        self.loaded.drain(..)
+    }
+
+    /// Takes the positions of the chunks unloaded since the last call.
+    pub fn drain_unloaded(&mut self) -> impl Iterator<Item = Vec2<i32>> + '_ {
+        self.unloaded.drain(..)
+    }
+
+    /// Takes the positions of the blocks changed since the last call.
+    pub fn drain_changed(&mut self) -> impl Iterator<Item = Vec3<i32>> + '_ {
+        self.changed.drain(..)
+    }
+
+    pub fn chunk(&self, pos: Vec2<i32>) -> Option<&Arc<Chunk>> {
+        self.world.chunk(pos)
+    }
+}
@@ -1,25 +1,41 @@
-use std::{sync::Arc, time::Instant};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use crate::{key_state::KeyState, render::Renderer, scene::Scene};
-use common::math::Vec2;
+use crate::{
+    client::Client, key_state::KeyState, render::Renderer, scene::Scene, singleplayer::Singleplayer,
+};
+use common::{
+    block::{BlockId, BlockRegistry},
+    math::Vec2,
+    thread_pool::ThreadPool,
+};
 use winit::{
-    event::{DeviceEvent, Event, KeyEvent},
+    event::{DeviceEvent, ElementState, Event, KeyEvent, MouseButton},
     event_loop::{ControlFlow, EventLoop},
-    keyboard::PhysicalKey,
+    keyboard::{KeyCode, PhysicalKey},
     window::{Window as Platform, WindowBuilder},
 };
 
+/// Time between two updates of the diagnostics shown in the window title.
+const TITLE_UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+
 pub struct Window {
     platform: Arc<Platform>,
     event_loop: Option<EventLoop<()>>,
     renderer: Renderer,
     scene: Scene,
+    blocks: Arc<BlockRegistry>,
+    /// The server hosting the world, if it runs in the game.
+    singleplayer: Option<Singleplayer>,
     cursor_grabbed: bool,
 }
 
 impl Window {
-    #[allow(clippy::new_without_default)]
-    pub fn new() -> Self {
+    /// Creates the game window, showing the world hosted by the server
+    /// `client` is connected to.
+    pub fn new(client: Client, singleplayer: Option<Singleplayer>) -> Self {
         let event_loop = EventLoop::new().unwrap();
         // ControlFlow::Poll continuously runs the event loop, even if the OS hasn't
         // dispatched any events. This is ideal for games and similar applications.
@@ -32,16 +48,21 @@ impl Window {
 
         let window = Arc::new(window);
 
-        let renderer = Renderer::new(&window);
+        let blocks = Arc::clone(client.blocks());
+        let pool = Arc::new(ThreadPool::with_available_parallelism());
+        let renderer = Renderer::new(&window, Arc::clone(&pool), Arc::clone(&blocks));
 
         let size = window.inner_size();
-        let scene = Scene::new(size.width as f32 / size.height as f32);
+        let aspect = size.width as f32 / size.height as f32;
+        let scene = Scene::new(aspect, client, Arc::clone(&blocks));
 
         Self {
             platform: window,
             event_loop: Some(event_loop),
             renderer,
             scene,
+            blocks,
+            singleplayer,
             cursor_grabbed: false,
         }
     }
@@ -50,6 +71,7 @@ impl Window {
         tracing::info!("Running explora...");
         let mut key_state = KeyState::default();
         let mut last_frame = Instant::now();
+        let mut last_title_update = Instant::now();
         const SENSITIVITY: f32 = 100.0;
         self.event_loop
             .take()
@@ -63,6 +85,10 @@ impl Window {
                         }
                         winit::event::WindowEvent::CloseRequested => {
                             tracing::info!("Application quit requested.");
+                            self.scene.quit();
+                            if let Some(singleplayer) = &mut self.singleplayer {
+                                singleplayer.stop();
+                            }
                             elwt.exit();
                         }
                         winit::event::WindowEvent::KeyboardInput {
@@ -80,7 +106,26 @@ impl Window {
                             {
                                 self.grab_cursor(!self.cursor_grabbed);
                             }
+                            if matches!(code, winit::keyboard::KeyCode::KeyG) && state.is_pressed()
+                            {
+                                self.renderer.cycle_meshing_mode();
+                            }
+                            if matches!(code, KeyCode::KeyF) && state.is_pressed() {
+                                self.scene.toggle_movement_mode();
+                            }
+                            if let Some(slot) = hotbar_slot(code).filter(|_| state.is_pressed()) {
+                                self.select_block(slot);
+                            }
                         }
+                        winit::event::WindowEvent::MouseInput {
+                            state: ElementState::Pressed,
+                            button,
+                            ..
+                        } if self.cursor_grabbed => match button {
+                            MouseButton::Left => self.scene.break_block(),
+                            MouseButton::Right => self.scene.place_block(),
+                            _ => (),
+                        },
                         _ => (),
                     }
                 }
@@ -99,14 +144,44 @@ impl Window {
                     let dt = last_frame.elapsed();
                     self.scene.set_movement_dir(key_state.dir());
                     self.scene.tick(dt.as_secs_f32());
+                    if let Some(reason) = self.scene.disconnect_reason().filter(|_| !elwt.exiting())
+                    {
+                        tracing::error!("Lost the connection to the server: {}", reason);
+                        elwt.exit();
+                        return;
+                    }
                     last_frame = Instant::now();
                     self.renderer.render(&mut self.scene);
+                    if last_title_update.elapsed() >= TITLE_UPDATE_INTERVAL {
+                        let stats = self.renderer.draw_stats();
+                        let title = if stats.gpu_culling {
+                            format!("explora - {} sections, culled on the GPU", stats.drawn)
+                        } else {
+                            format!(
+                                "explora - {} sections drawn, {} culled",
+                                stats.drawn, stats.culled
+                            )
+                        };
+                        self.platform.set_title(&title);
+                        last_title_update = Instant::now();
+                    }
                 }
                 _ => (),
             })
             .unwrap();
     }
 
+    /// Selects the block placed by the player, counting from the first one
+    /// after air.
+    fn select_block(&mut self, slot: usize) {
+        if slot + 1 >= self.blocks.len() {
+            return;
+        }
+        let block = BlockId::from_raw(slot as u16 + 1);
+        tracing::info!("Selected {}", self.blocks.get(block).name);
+        self.scene.select_block(block);
+    }
+
     pub fn grab_cursor(&mut self, value: bool) {
         self.platform.set_cursor_visible(!value);
         let mode = if value {
@@ -120,3 +195,19 @@ impl Window {
         }
     }
 }
+
+/// Maps the digit keys to the slots of the hotbar.
+fn hotbar_slot(code: KeyCode) -> Option<usize> {
+    const KEYS: [KeyCode; 9] = [
+        KeyCode::Digit1,
+        KeyCode::Digit2,
+        KeyCode::Digit3,
+        KeyCode::Digit4,
+        KeyCode::Digit5,
+        KeyCode::Digit6,
+        KeyCode::Digit7,
+        KeyCode::Digit8,
+        KeyCode::Digit9,
+    ];
+    KEYS.iter().position(|key| *key == code)
+}
@@ -1,5 +1,87 @@
+use std::{
+    io::BufRead,
+    sync::{
+        mpsc::{self, RecvTimeoutError},
+        Arc,
+    },
+    time::Duration,
+};
+
+use common::{block::BlockRegistry, save::WorldSave, terrain::HeightmapGenerator};
+use server::{Server, ServerConfig, WORLD_DIR, WORLD_SEED};
+
+/// Address the server listens on, unless another one is given as the first
+/// argument.
+const DEFAULT_ADDRESS: &str = "0.0.0.0:30000";
+
 fn main() {
     common_log::init();
 
-    tracing::info!("Hello World!");
+    let blocks = match BlockRegistry::load("assets/blocks/") {
+        Ok(blocks) => blocks,
+        Err(e) => {
+            tracing::error!("Could not load the block definitions: {:?}", e);
+            return;
+        }
+    };
+    let (save, blocks) = match WorldSave::open(WORLD_DIR, WORLD_SEED, &blocks) {
+        Ok(opened) => opened,
+        Err(e) => {
+            tracing::error!("Could not open the world in {}: {:?}", WORLD_DIR, e);
+            return;
+        }
+    };
+    let blocks = Arc::new(blocks);
+    let generator = HeightmapGenerator::new(save.seed(), &blocks);
+    let mut server = Server::new(
+        ServerConfig::default(),
+        blocks,
+        Arc::new(generator),
+        Some(Arc::new(save)),
+    );
+
+    let address = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| DEFAULT_ADDRESS.to_owned());
+    if let Err(e) = server.listen(&address) {
+        tracing::error!("Could not listen on {}: {}", address, e);
+        return;
+    }
+
+    tracing::info!("Hosting the world in {}", WORLD_DIR);
+    let server = server.start();
+    let console = spawn_console();
+    while server.is_running() {
+        match console.recv_timeout(Duration::from_millis(100)) {
+            Ok(line) => match server.execute(&line) {
+                Ok(output) if output.is_empty() => {}
+                Ok(output) => println!("{}", output),
+                Err(e) => tracing::error!("{}", e),
+            },
+            Err(RecvTimeoutError::Timeout) => {}
+            // Without a console the server runs until it is stopped some
+            // other way.
+            Err(RecvTimeoutError::Disconnected) => std::thread::sleep(Duration::from_millis(100)),
+        }
+    }
+    server.join();
+}
+
+/// Reads command lines from the standard input on another thread.
+fn spawn_console() -> mpsc::Receiver<String> {
+    let (tx, rx) = mpsc::channel();
+    std::thread::Builder::new()
+        .name("console".into())
+        .spawn(move || {
+            for line in std::io::stdin().lock().lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                if tx.send(line).is_err() {
+                    break;
+                }
+            }
+        })
+        .expect("failed to spawn the console thread");
+    rx
 }
@@ -0,0 +1,346 @@
+//! Commands operators run to administer a server.
+
+use std::{collections::BTreeMap, fmt};
+
+use common::{chunk::Chunk, math::Vec3, world::World};
+
+use crate::{network::ClientId, Server};
+
+/// Runs a command with the given arguments, returning the text to show to
+/// the operator.
+pub type CommandHandler = fn(&mut Server, &[&str]) -> Result<String, CommandError>;
+
+#[derive(Debug, PartialEq)]
+pub enum CommandError {
+    UnknownCommand(String),
+    /// The arguments do not match the usage of the command.
+    Usage(&'static str),
+    UnknownPlayer(String),
+    UnknownBlock(String),
+    /// The position is above or below the world.
+    OutOfWorld(Vec3<i32>),
+    /// The server stopped before running the command.
+    ServerStopped,
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UnknownCommand(name) => write!(f, "Unknown command {}, see help", name),
+            Self::Usage(usage) => write!(f, "Invalid arguments, expected {}", usage),
+            Self::UnknownPlayer(name) => write!(f, "No player named {} is online", name),
+            Self::UnknownBlock(name) => write!(f, "Unknown block {}", name),
+            Self::OutOfWorld(pos) => write!(f, "{:?} is outside of the world", pos),
+            Self::ServerStopped => write!(f, "The server is stopped"),
+        }
+    }
+}
+
+/// A command known to a [CommandRegistry].
+#[derive(Clone, Copy)]
+pub struct Command {
+    /// Arguments of the command, shown when they are invalid.
+    pub usage: &'static str,
+    pub description: &'static str,
+    pub handler: CommandHandler,
+}
+
+/// The commands a server understands, by name.
+pub struct CommandRegistry {
+    commands: BTreeMap<&'static str, Command>,
+}
+
+impl CommandRegistry {
+    /// Creates a registry without any command.
+    pub fn empty() -> Self {
+        Self {
+            commands: BTreeMap::new(),
+        }
+    }
+
+    /// Adds a command, replacing any other one with the same name.
+    pub fn register(&mut self, name: &'static str, command: Command) {
+        self.commands.insert(name, command);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Command> {
+        self.commands.get(name)
+    }
+
+    /// Iterates over every command, in alphabetical order.
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, &Command)> {
+        self.commands.iter().map(|(name, command)| (*name, command))
+    }
+}
+
+impl Default for CommandRegistry {
+    /// Creates a registry with the built-in commands.
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        let builtins: [(&str, &str, &str, CommandHandler); 10] = [
+            ("help", "", "Lists the commands", help),
+            ("stop", "", "Saves the world and stops the server", stop),
+            ("save", "", "Saves the modified chunks", save),
+            ("list", "", "Lists the connected players", list),
+            ("kick", "<player> [reason]", "Disconnects a player", kick),
+            ("say", "<message>", "Sends a chat message to everyone", say),
+            ("tp", "<player> <x> <y> <z>", "Moves a player", tp),
+            (
+                "setblock",
+                "<x> <y> <z> <block>",
+                "Changes a block",
+                setblock,
+            ),
+            ("seed", "", "Shows the seed of the world", seed),
+            ("tps", "", "Shows how fast the server runs", tps),
+        ];
+        for (name, usage, description, handler) in builtins {
+            registry.register(
+                name,
+                Command {
+                    usage,
+                    description,
+                    handler,
+                },
+            );
+        }
+        registry
+    }
+}
+
+/// Splits a command line into the name of the command and its arguments.
+pub fn parse(line: &str) -> Option<(&str, Vec<&str>)> {
+    let mut words = line.split_whitespace();
+    let name = words.next()?;
+    Some((name, words.collect()))
+}
+
+fn help(server: &mut Server, _: &[&str]) -> Result<String, CommandError> {
+    Ok(server
+        .commands()
+        .iter()
+        .map(|(name, command)| match command.usage {
+            "" => format!("{} - {}", name, command.description),
+            usage => format!("{} {} - {}", name, usage, command.description),
+        })
+        .collect::<Vec<_>>()
+        .join("\n"))
+}
+
+fn stop(server: &mut Server, _: &[&str]) -> Result<String, CommandError> {
+    server.request_stop();
+    Ok("Stopping the server".to_owned())
+}
+
+fn save(server: &mut Server, _: &[&str]) -> Result<String, CommandError> {
+    let modified = server.world().modified().count();
+    server.save();
+    Ok(format!("Saved {} chunks", modified))
+}
+
+fn list(server: &mut Server, _: &[&str]) -> Result<String, CommandError> {
+    let mut lines = vec![format!(
+        "{} players online",
+        server.network().sessions().count()
+    )];
+    for session in server.network().sessions() {
+        let pos = server.player(session.id()).map(|player| player.pos);
+        lines.push(format!(
+            "{} ({}) at {:?}",
+            session.name(),
+            session.peer(),
+            pos.unwrap_or_default()
+        ));
+    }
+    Ok(lines.join("\n"))
+}
+
+fn kick(server: &mut Server, args: &[&str]) -> Result<String, CommandError> {
+    let [name, reason @ ..] = args else {
+        return Err(CommandError::Usage("<player> [reason]"));
+    };
+    let id = find_player(server, name)?;
+    let reason = if reason.is_empty() {
+        "Kicked by an operator".to_owned()
+    } else {
+        reason.join(" ")
+    };
+    server.network_mut().disconnect(id, &reason);
+    Ok(format!("Kicked {}", name))
+}
+
+fn say(server: &mut Server, args: &[&str]) -> Result<String, CommandError> {
+    if args.is_empty() {
+        return Err(CommandError::Usage("<message>"));
+    }
+    server.broadcast_chat(format!("[Server] {}", args.join(" ")));
+    Ok(String::new())
+}
+
+fn tp(server: &mut Server, args: &[&str]) -> Result<String, CommandError> {
+    const USAGE: &str = "<player> <x> <y> <z>";
+    let [name, x, y, z] = args else {
+        return Err(CommandError::Usage(USAGE));
+    };
+    let id = find_player(server, name)?;
+    let pos = parse_vec3::<f32>([x, y, z])
+        .filter(|pos| pos.map(f32::is_finite).reduce_and())
+        .ok_or(CommandError::Usage(USAGE))?;
+    server.teleport(id, pos);
+    Ok(format!("Teleported {} to {:?}", name, pos))
+}
+
+fn setblock(server: &mut Server, args: &[&str]) -> Result<String, CommandError> {
+    const USAGE: &str = "<x> <y> <z> <block>";
+    let [x, y, z, block] = args else {
+        return Err(CommandError::Usage(USAGE));
+    };
+    let pos = parse_vec3::<i32>([x, y, z]).ok_or(CommandError::Usage(USAGE))?;
+    let id = server
+        .blocks()
+        .id(block)
+        .ok_or_else(|| CommandError::UnknownBlock(block.to_string()))?;
+    // Checked first so that no chunk is loaded for nothing.
+    if Chunk::section_index(pos.y).is_none() {
+        return Err(CommandError::OutOfWorld(pos));
+    }
+    server.load_chunk(World::chunk_pos(pos));
+    server
+        .set_block(pos, id)
+        .ok_or(CommandError::OutOfWorld(pos))?;
+    Ok(format!("Set {:?} to {}", pos, block))
+}
+
+fn seed(server: &mut Server, _: &[&str]) -> Result<String, CommandError> {
+    Ok(format!("Seed: {}", server.generator().seed()))
+}
+
+fn tps(server: &mut Server, _: &[&str]) -> Result<String, CommandError> {
+    let stats = server.stats();
+    Ok(format!(
+        "{:.1} TPS (target {}), {:?} per tick, {} overruns",
+        stats.tps,
+        server.config().tps,
+        stats.mean_duration,
+        stats.overruns
+    ))
+}
+
+fn find_player(server: &Server, name: &str) -> Result<ClientId, CommandError> {
+    server
+        .network()
+        .sessions()
+        .find(|session| session.name() == name)
+        .map(|session| session.id())
+        .ok_or_else(|| CommandError::UnknownPlayer(name.to_owned()))
+}
+
+fn parse_vec3<T: std::str::FromStr>(coords: [&&str; 3]) -> Option<Vec3<T>> {
+    let [x, y, z] = coords.map(|coord| coord.parse().ok());
+    Some(Vec3::new(x?, y?, z?))
+}
+
+#[cfg(test)]
+mod tests {
+    use common::math::Vec2;
+
+    use super::*;
+    use crate::tests::{join, server};
+
+    #[test]
+    fn rejects_unknown_commands() {
+        let mut server = server(None);
+        assert_eq!(
+            server.execute("fly away"),
+            Err(CommandError::UnknownCommand("fly".to_owned()))
+        );
+        assert_eq!(server.execute("  "), Ok(String::new()));
+    }
+
+    #[test]
+    fn checks_argument_counts() {
+        let mut server = server(None);
+        for line in [
+            "kick",
+            "say",
+            "tp",
+            "tp a 1 2",
+            "setblock 1 2 3",
+            "setblock 1 2 3 stone x",
+        ] {
+            assert!(
+                matches!(server.execute(line), Err(CommandError::Usage(_))),
+                "{}",
+                line
+            );
+        }
+    }
+
+    #[test]
+    fn sets_blocks() {
+        let mut server = server(None);
+        assert_eq!(
+            server.execute("setblock 1 2 3 lava"),
+            Err(CommandError::UnknownBlock("lava".to_owned()))
+        );
+        assert_eq!(
+            server.execute("setblock 1 2 x stone"),
+            Err(CommandError::Usage("<x> <y> <z> <block>"))
+        );
+
+        let pos = Vec3::new(-1, 10, 40);
+        let stone = server.blocks().id("stone").unwrap();
+        assert!(server.execute("setblock -1 10 40 stone").is_ok());
+        assert_eq!(server.world().get_block(pos), Some(stone));
+        assert!(server.world().is_modified(World::chunk_pos(pos)));
+    }
+
+    #[test]
+    fn refuses_blocks_out_of_the_world() {
+        let mut server = server(None);
+        let height = Chunk::SIZE.y as i32;
+        for y in [-1, height] {
+            let pos = Vec3::new(0, y, 0);
+            assert_eq!(
+                server.execute(&format!("setblock 0 {} 0 stone", y)),
+                Err(CommandError::OutOfWorld(pos))
+            );
+        }
+        assert!(!server.world().contains_chunk(Vec2::zero()));
+    }
+
+    #[test]
+    fn teleports_players() {
+        let mut server = server(None);
+        assert_eq!(
+            server.execute("tp alice 1 2 3"),
+            Err(CommandError::UnknownPlayer("alice".to_owned()))
+        );
+
+        let (_client, id) = join(&mut server, "alice");
+        for line in ["tp alice NaN 2 3", "tp alice 1 inf 3", "tp alice 1 2 -inf"] {
+            assert_eq!(
+                server.execute(line),
+                Err(CommandError::Usage("<player> <x> <y> <z>")),
+                "{}",
+                line
+            );
+        }
+        assert!(server.execute("tp alice 1 2 3").is_ok());
+        assert_eq!(server.player(id).unwrap().pos, Vec3::new(1.0, 2.0, 3.0));
+    }
+
+    #[test]
+    fn kicks_players() {
+        let mut server = server(None);
+        assert_eq!(
+            server.execute("kick bob"),
+            Err(CommandError::UnknownPlayer("bob".to_owned()))
+        );
+
+        let (_client, id) = join(&mut server, "bob");
+        assert!(server.execute("kick bob Too loud").is_ok());
+        server.step();
+        assert!(server.player(id).is_none());
+    }
+}
@@ -0,0 +1,82 @@
+use std::collections::HashSet;
+
+use common::math::Vec2;
+
+/// The chunks a client has received and keeps up to date.
+pub struct Interest {
+    /// Radius, in chunk columns, of the area around the player the client
+    /// wants to know about.
+    pub view_distance: i32,
+    /// See [`ServerConfig::unload_margin`](crate::ServerConfig::unload_margin).
+    pub unload_margin: i32,
+    sent: HashSet<Vec2<i32>>,
+}
+
+impl Interest {
+    pub fn new(view_distance: i32, unload_margin: i32) -> Self {
+        Self {
+            view_distance,
+            unload_margin,
+            sent: HashSet::new(),
+        }
+    }
+
+    /// Whether the client has the chunk at the given column.
+    pub fn contains(&self, pos: Vec2<i32>) -> bool {
+        self.sent.contains(&pos)
+    }
+
+    /// Records that the chunk at the given column was sent to the client.
+    pub fn insert(&mut self, pos: Vec2<i32>) {
+        self.sent.insert(pos);
+    }
+
+    /// Number of chunks the client has.
+    pub fn len(&self) -> usize {
+        self.sent.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sent.is_empty()
+    }
+
+    /// Whether the chunk at the given column is in view of `center`.
+    pub fn in_view(&self, center: Vec2<i32>, pos: Vec2<i32>) -> bool {
+        pos.distance_squared(center) <= self.view_distance.pow(2)
+    }
+
+    /// Returns the chunks in view of `center` the client does not have yet,
+    /// nearest first.
+    pub fn missing(&self, center: Vec2<i32>) -> Vec<Vec2<i32>> {
+        let radius = self.view_distance;
+        let mut missing = Vec::new();
+        for x in -radius..=radius {
+            for z in -radius..=radius {
+                let offset = Vec2::new(x, z);
+                if offset.magnitude_squared() <= radius.pow(2)
+                    && !self.sent.contains(&(center + offset))
+                {
+                    missing.push(center + offset);
+                }
+            }
+        }
+        missing.sort_by_key(|pos| pos.distance_squared(center));
+        missing
+    }
+
+    /// Forgets the chunks that are too far from `center`, returning them so
+    /// the client can be told to unload them.
+    pub fn remove_distant(&mut self, center: Vec2<i32>) -> Vec<Vec2<i32>> {
+        let radius = self.view_distance + self.unload_margin;
+        let distant = self
+            .sent
+            .iter()
+            .copied()
+            .filter(|pos| pos.distance_squared(center) > radius.pow(2))
+            .collect::<Vec<_>>();
+        for pos in &distant {
+            self.sent.remove(pos);
+        }
+        distant
+    }
+}
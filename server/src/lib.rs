@@ -1,2 +1,742 @@
-pub struct Server {}
-impl Server {}
+pub mod command;
+pub mod interest;
+pub mod network;
+pub mod player;
+pub mod tick;
+
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    net::{SocketAddr, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use common::{
+    block::{BlockId, BlockRegistry},
+    chunk::Chunk,
+    codec,
+    math::{Vec2, Vec3},
+    protocol::{ClientMessage, ServerMessage},
+    save::WorldSave,
+    terrain::WorldGenerator,
+    thread_pool::ThreadPool,
+    world::World,
+};
+
+use command::{CommandError, CommandRegistry};
+use network::{ClientId, Network, NetworkEvent};
+use player::{Player, SPAWN_POS};
+use tick::TickStats;
+
+/// If the server falls this far behind schedule it stops trying to catch up
+/// and skips the missed ticks instead.
+const MAX_LAG: Duration = Duration::from_secs(2);
+/// Chunks requested from the workers for each player per tick.
+const MAX_CHUNKS_PER_TICK: usize = 8;
+/// Chunks loaded or generated at once, for all the players.
+const MAX_LOADING_CHUNKS: usize = 64;
+
+// TODO: make these configurable
+/// Directory the world is saved in, relative to the working directory.
+pub const WORLD_DIR: &str = "saves/world";
+/// Seed of newly created worlds.
+pub const WORLD_SEED: u32 = 0;
+
+#[derive(Clone, Copy, Debug)]
+pub struct ServerConfig {
+    /// Ticks run per second.
+    pub tps: u32,
+    /// Radius, in chunk columns, of the area sent to each player.
+    pub view_distance: i32,
+    /// Distance, in chunk columns, chunks can go past the view distance
+    /// before being unloaded, so moving back and forth along the edge of the
+    /// view does not send the same chunks over and over.
+    pub unload_margin: i32,
+    /// Bytes of chunk data sent to each player per tick.
+    ///
+    /// At least one chunk is sent every tick, even if it does not fit.
+    pub chunk_bytes_per_tick: usize,
+    /// Time between two saves of the world, when the server runs on its own
+    /// thread.
+    pub autosave_interval: Duration,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            tps: 20,
+            view_distance: 8,
+            unload_margin: 2,
+            chunk_bytes_per_tick: 64 * 1024,
+            autosave_interval: Duration::from_secs(60),
+        }
+    }
+}
+
+/// The authoritative simulation of a world.
+///
+/// The simulation advances in fixed steps called ticks. Embedders can either
+/// [`step`](Server::step) it manually, which makes the simulation
+/// deterministic, or [`start`](Server::start) it on its own thread to run at
+/// [`ServerConfig::tps`].
+pub struct Server {
+    config: ServerConfig,
+    blocks: Arc<BlockRegistry>,
+    world: World,
+    generator: Arc<dyn WorldGenerator>,
+    save: Option<Arc<WorldSave>>,
+    /// Loads and generates chunks off the tick thread.
+    workers: ThreadPool,
+    /// Chunks the workers were asked for and did not return yet.
+    loading: HashSet<Vec2<i32>>,
+    loaded_tx: Sender<(Vec2<i32>, Chunk)>,
+    loaded_rx: Receiver<(Vec2<i32>, Chunk)>,
+    network: Network,
+    players: HashMap<ClientId, Player>,
+    /// Blocks changed during this tick, to send to the players.
+    block_changes: Vec<(Vec3<i32>, BlockId)>,
+    commands: CommandRegistry,
+    /// Set by [`Server::request_stop`].
+    stop_requested: bool,
+    stats: TickStats,
+}
+
+impl Server {
+    pub fn new(
+        config: ServerConfig,
+        blocks: Arc<BlockRegistry>,
+        generator: Arc<dyn WorldGenerator>,
+        save: Option<Arc<WorldSave>>,
+    ) -> Self {
+        assert!(
+            config.tps > 0,
+            "the server must run at least one tick per second"
+        );
+        let (loaded_tx, loaded_rx) = mpsc::channel();
+        Self {
+            config,
+            network: Network::new(generator.seed(), blocks.names()),
+            blocks,
+            world: World::new(),
+            players: HashMap::new(),
+            block_changes: Vec::new(),
+            commands: CommandRegistry::default(),
+            stop_requested: false,
+            generator,
+            save,
+            workers: ThreadPool::with_available_parallelism(),
+            loading: HashSet::new(),
+            loaded_tx,
+            loaded_rx,
+            stats: TickStats::default(),
+        }
+    }
+
+    pub fn config(&self) -> &ServerConfig {
+        &self.config
+    }
+
+    pub fn blocks(&self) -> &Arc<BlockRegistry> {
+        &self.blocks
+    }
+
+    pub fn world(&self) -> &World {
+        &self.world
+    }
+
+    pub fn world_mut(&mut self) -> &mut World {
+        &mut self.world
+    }
+
+    pub fn generator(&self) -> &Arc<dyn WorldGenerator> {
+        &self.generator
+    }
+
+    pub fn network(&self) -> &Network {
+        &self.network
+    }
+
+    pub fn network_mut(&mut self) -> &mut Network {
+        &mut self.network
+    }
+
+    pub fn commands(&self) -> &CommandRegistry {
+        &self.commands
+    }
+
+    /// Gives access to the commands, to add new ones.
+    pub fn commands_mut(&mut self) -> &mut CommandRegistry {
+        &mut self.commands
+    }
+
+    pub fn player(&self, id: ClientId) -> Option<&Player> {
+        self.players.get(&id)
+    }
+
+    /// Starts accepting clients on the given address, returning the address
+    /// actually bound.
+    pub fn listen(&mut self, addr: impl ToSocketAddrs) -> io::Result<SocketAddr> {
+        self.network.listen(addr)
+    }
+
+    /// Number of ticks run so far.
+    pub fn tick(&self) -> u64 {
+        self.stats.ticks
+    }
+
+    pub fn stats(&self) -> &TickStats {
+        &self.stats
+    }
+
+    /// Time between the start of two ticks.
+    pub fn tick_interval(&self) -> Duration {
+        Duration::from_secs(1) / self.config.tps
+    }
+
+    /// Runs a single tick.
+    pub fn step(&mut self) {
+        let start = Instant::now();
+        for event in self.network.poll() {
+            match event {
+                NetworkEvent::Joined(id) => {
+                    let Some(session) = self.network.session_mut(id) else {
+                        continue;
+                    };
+                    session.send(ServerMessage::PlayerPosition { pos: SPAWN_POS });
+                    let name = session.name().to_owned();
+                    let player = Player::new(
+                        SPAWN_POS,
+                        self.config.view_distance,
+                        self.config.unload_margin,
+                    );
+                    self.players.insert(id, player);
+                    self.broadcast_chat(format!("{} joined the game", name));
+                }
+                NetworkEvent::Left { id, .. } => {
+                    self.players.remove(&id);
+                }
+            }
+        }
+        self.handle_messages();
+        self.receive_chunks();
+        self.send_block_changes();
+        self.send_chunks();
+        self.unload_chunks();
+        self.network.flush();
+        self.stats
+            .record_tick(start.elapsed(), self.tick_interval());
+    }
+
+    /// Sends a chat message to every client.
+    pub fn broadcast_chat(&mut self, message: String) {
+        tracing::info!("[chat] {}", message);
+        self.network.broadcast(ServerMessage::Chat { message });
+    }
+
+    /// Moves a player and tells its client.
+    pub fn teleport(&mut self, id: ClientId, pos: Vec3<f32>) {
+        let Some(player) = self.players.get_mut(&id) else {
+            return;
+        };
+        player.pos = pos;
+        if let Some(session) = self.network.session_mut(id) {
+            session.send(ServerMessage::PlayerPosition { pos });
+        }
+    }
+
+    /// Runs a command line, returning the text to show to the operator.
+    pub fn execute(&mut self, line: &str) -> Result<String, CommandError> {
+        let Some((name, args)) = command::parse(line) else {
+            return Ok(String::new());
+        };
+        let command = *self
+            .commands
+            .get(name)
+            .ok_or_else(|| CommandError::UnknownCommand(name.to_owned()))?;
+        (command.handler)(self, &args)
+    }
+
+    /// Asks the server to stop at the end of the current tick, when it runs
+    /// on its own thread.
+    pub fn request_stop(&mut self) {
+        self.stop_requested = true;
+    }
+
+    /// Returns the chunk at the given column, loading it from the save or
+    /// generating it right away if needed.
+    pub fn load_chunk(&mut self, pos: Vec2<i32>) -> Arc<Chunk> {
+        if let Some(chunk) = self.world.chunk(pos) {
+            return Arc::clone(chunk);
+        }
+        // The workers may be loading it too, their copy is dropped.
+        self.loading.remove(&pos);
+        let chunk = load_or_generate(&*self.generator, self.save.as_deref(), pos);
+        self.world.insert_chunk(pos, chunk);
+        Arc::clone(self.world.chunk(pos).unwrap())
+    }
+
+    /// Asks the workers for the chunk at the given column, unless it is
+    /// loaded or being loaded already.
+    ///
+    /// Returns whether a new request was made, which fails when too many
+    /// chunks are being loaded.
+    fn request_chunk(&mut self, pos: Vec2<i32>) -> bool {
+        if self.loading.len() >= MAX_LOADING_CHUNKS
+            || self.loading.contains(&pos)
+            || self.world.contains_chunk(pos)
+        {
+            return false;
+        }
+        self.loading.insert(pos);
+        let generator = Arc::clone(&self.generator);
+        let save = self.save.clone();
+        let loaded = self.loaded_tx.clone();
+        self.workers.execute(move || {
+            let chunk = load_or_generate(&*generator, save.as_deref(), pos);
+            let _ = loaded.send((pos, chunk));
+        });
+        true
+    }
+
+    /// Adds the chunks returned by the workers to the world.
+    fn receive_chunks(&mut self) {
+        let loaded = self.loaded_rx.try_iter().collect::<Vec<_>>();
+        for (pos, chunk) in loaded {
+            // Chunks loaded since by [`Server::load_chunk`] are not expected
+            // anymore, and may have been modified.
+            if self.loading.remove(&pos) {
+                self.world.insert_chunk(pos, chunk);
+            }
+        }
+    }
+
+    /// Sets the block at the given world position, returning the previous
+    /// one. The change is sent to the players having the chunk.
+    ///
+    /// Returns `None` and does nothing if the chunk is not loaded.
+    pub fn set_block(&mut self, pos: Vec3<i32>, block: BlockId) -> Option<BlockId> {
+        let previous = self.world.set_block(pos, block)?;
+        if previous != block {
+            self.block_changes.push((pos, block));
+        }
+        Some(previous)
+    }
+
+    /// Handles the messages received from the clients during this tick.
+    fn handle_messages(&mut self) {
+        let mut chat = Vec::new();
+        let mut edits = Vec::new();
+        for session in self.network.sessions_mut() {
+            let player = self.players.get_mut(&session.id()).unwrap();
+            while let Some(message) = session.recv() {
+                match message {
+                    ClientMessage::PlayerPosition { pos }
+                        if pos.map(f32::is_finite).reduce_and() =>
+                    {
+                        player.pos = pos;
+                    }
+                    ClientMessage::SetBlock { pos, block }
+                        if (block.raw() as usize) < self.blocks.len()
+                            && player.interest.contains(World::chunk_pos(pos)) =>
+                    {
+                        edits.push((pos, block));
+                    }
+                    // The client already applied the change, so it is told
+                    // the actual block to undo it.
+                    ClientMessage::SetBlock { pos, .. } => {
+                        if let Some(block) = self.world.get_block(pos) {
+                            session.send(ServerMessage::BlockChange { pos, block });
+                        }
+                    }
+                    ClientMessage::Chat { message } => {
+                        chat.push(format!("<{}> {}", session.name(), message));
+                    }
+                    _ => {}
+                }
+            }
+        }
+        for (pos, block) in edits {
+            self.set_block(pos, block);
+        }
+        for message in chat {
+            self.broadcast_chat(message);
+        }
+    }
+
+    /// Sends the blocks changed during this tick to the players having
+    /// their chunk. Players get the others with the chunk itself.
+    fn send_block_changes(&mut self) {
+        for (pos, block) in self.block_changes.drain(..) {
+            let chunk_pos = World::chunk_pos(pos);
+            for (id, player) in &self.players {
+                if !player.interest.contains(chunk_pos) {
+                    continue;
+                }
+                if let Some(session) = self.network.session_mut(*id) {
+                    session.send(ServerMessage::BlockChange { pos, block });
+                }
+            }
+        }
+    }
+
+    /// Unloads the chunks that left the view of each player, and sends it
+    /// the nearest chunks it does not have yet, within the bandwidth budget.
+    ///
+    /// Only loaded chunks are sent; the workers are asked for the others.
+    fn send_chunks(&mut self) {
+        let ids = self.players.keys().copied().collect::<Vec<_>>();
+        for id in ids {
+            let player = self.players.get_mut(&id).unwrap();
+            let center = player.chunk_pos();
+            let unloaded = player.interest.remove_distant(center);
+            let missing = player.interest.missing(center);
+            let Some(session) = self.network.session_mut(id) else {
+                continue;
+            };
+            for pos in unloaded {
+                session.send(ServerMessage::UnloadChunk { pos });
+            }
+
+            let mut budget = self.config.chunk_bytes_per_tick;
+            let mut sent = 0;
+            let mut requested = 0;
+            for pos in missing {
+                let Some(chunk) = self.world.chunk(pos) else {
+                    if requested < MAX_CHUNKS_PER_TICK && self.request_chunk(pos) {
+                        requested += 1;
+                    }
+                    continue;
+                };
+                let len = codec::body_len(chunk);
+                if sent > 0 && len > budget {
+                    break;
+                }
+                let chunk = Arc::clone(chunk);
+                budget = budget.saturating_sub(len);
+                sent += 1;
+                self.players.get_mut(&id).unwrap().interest.insert(pos);
+                if let Some(session) = self.network.session_mut(id) {
+                    session.send(ServerMessage::ChunkData { pos, chunk });
+                }
+            }
+        }
+    }
+
+    /// Removes the chunks no player has or is about to receive from the
+    /// world, saving the modified ones first.
+    ///
+    /// Modified chunks stay loaded if they cannot be saved.
+    fn unload_chunks(&mut self) {
+        let centers = self
+            .players
+            .values()
+            .map(|player| (player, player.chunk_pos()))
+            .collect::<Vec<_>>();
+        let unused = self
+            .world
+            .chunks()
+            .map(|(pos, _)| pos)
+            .filter(|pos| {
+                !centers.iter().any(|(player, center)| {
+                    player.interest.contains(*pos) || player.interest.in_view(*center, *pos)
+                })
+            })
+            .collect::<Vec<_>>();
+        for pos in unused {
+            if self.world.is_modified(pos) {
+                let Some(save) = &self.save else {
+                    continue;
+                };
+                if let Err(e) = save.save_chunk(pos, self.world.chunk(pos).unwrap()) {
+                    tracing::error!("Could not save chunk {}: {:?}", pos, e);
+                    continue;
+                }
+            }
+            self.world.remove_chunk(pos);
+        }
+    }
+
+    /// Writes every modified chunk to the [WorldSave], if any.
+    pub fn save(&mut self) {
+        let Some(save) = &self.save else {
+            return;
+        };
+        let modified = self.world.modified().collect::<Vec<_>>();
+        for chunk_pos in modified {
+            let Some(chunk) = self.world.chunk(chunk_pos) else {
+                continue;
+            };
+            match save.save_chunk(chunk_pos, chunk) {
+                Ok(()) => self.world.clear_modified(chunk_pos),
+                Err(e) => tracing::error!("Could not save chunk {}: {:?}", chunk_pos, e),
+            }
+        }
+    }
+
+    /// Runs the server on a new thread until it is stopped through the
+    /// returned handle.
+    pub fn start(self) -> ServerHandle {
+        let running = Arc::new(AtomicBool::new(true));
+        let (console, commands) = mpsc::channel();
+        let thread = std::thread::Builder::new()
+            .name("server".into())
+            .spawn({
+                let running = Arc::clone(&running);
+                move || self.run(&running, &commands)
+            })
+            .expect("failed to spawn the server thread");
+        ServerHandle {
+            running,
+            console,
+            thread,
+        }
+    }
+
+    /// Runs ticks at a fixed rate while `running` is set and no stop was
+    /// requested, then saves the world.
+    ///
+    /// Commands received from `commands` are run before each tick.
+    fn run(mut self, running: &AtomicBool, commands: &Receiver<ConsoleCommand>) -> Self {
+        tracing::info!("Server running at {} TPS", self.config.tps);
+        let interval = self.tick_interval();
+        let mut next_tick = Instant::now();
+        let mut last_tick = None;
+        let mut last_save = Instant::now();
+        while running.load(Ordering::Relaxed) && !self.stop_requested {
+            let now = Instant::now();
+            if now < next_tick {
+                std::thread::sleep(next_tick - now);
+                continue;
+            }
+            if now - next_tick > MAX_LAG {
+                let skipped = ((now - next_tick).as_nanos() / interval.as_nanos()) as u64;
+                tracing::warn!(
+                    "Server is {:?} behind, skipping {} ticks",
+                    now - next_tick,
+                    skipped
+                );
+                next_tick = now;
+            }
+            if let Some(last_tick) = last_tick {
+                self.stats.record_interval(now - last_tick);
+            }
+            last_tick = Some(now);
+
+            for (line, reply) in commands.try_iter() {
+                let _ = reply.send(self.execute(&line));
+            }
+            self.step();
+            if self.stats.last_duration > interval {
+                tracing::warn!(
+                    "Tick {} took {:?}, {:?} over the {:?} budget",
+                    self.tick(),
+                    self.stats.last_duration,
+                    self.stats.last_duration - interval,
+                    interval,
+                );
+            }
+            if last_save.elapsed() >= self.config.autosave_interval {
+                self.save();
+                last_save = Instant::now();
+            }
+            next_tick += interval;
+        }
+
+        tracing::info!("Server stopped after {} ticks", self.tick());
+        self.network.disconnect_all("Server closed");
+        self.save();
+        self
+    }
+}
+
+/// Loads the chunk at the given column from the save, or generates it if it
+/// was never saved.
+fn load_or_generate(
+    generator: &dyn WorldGenerator,
+    save: Option<&WorldSave>,
+    pos: Vec2<i32>,
+) -> Chunk {
+    let saved = save.and_then(|save| {
+        save.load_chunk(pos).unwrap_or_else(|e| {
+            tracing::error!("Could not load chunk {}: {:?}", pos, e);
+            None
+        })
+    });
+    saved.unwrap_or_else(|| generator.generate(pos))
+}
+
+/// A command line sent to a running server, with where to send its result.
+type ConsoleCommand = (String, Sender<Result<String, CommandError>>);
+
+/// A [Server] running on its own thread.
+pub struct ServerHandle {
+    running: Arc<AtomicBool>,
+    console: Sender<ConsoleCommand>,
+    thread: JoinHandle<Server>,
+}
+
+impl ServerHandle {
+    pub fn is_running(&self) -> bool {
+        !self.thread.is_finished()
+    }
+
+    /// Runs a command line before the next tick, blocking until it ran.
+    ///
+    /// See [`Server::execute`].
+    pub fn execute(&self, line: &str) -> Result<String, CommandError> {
+        let (reply, result) = mpsc::channel();
+        self.console
+            .send((line.to_owned(), reply))
+            .map_err(|_| CommandError::ServerStopped)?;
+        result.recv().map_err(|_| CommandError::ServerStopped)?
+    }
+
+    /// Stops the server after its current tick, returning it once it has
+    /// saved the world.
+    pub fn stop(self) -> Server {
+        self.running.store(false, Ordering::Relaxed);
+        self.join()
+    }
+
+    /// Waits for the server to stop.
+    pub fn join(self) -> Server {
+        self.thread.join().expect("the server thread panicked")
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use common::{
+        block::BlockDef,
+        net::{ChannelConnection, Connection},
+        protocol::PROTOCOL_VERSION,
+    };
+
+    use super::*;
+
+    /// Generates chunks with a single layer of stone at the bottom.
+    struct Flat {
+        stone: BlockId,
+    }
+
+    impl WorldGenerator for Flat {
+        fn seed(&self) -> u32 {
+            5
+        }
+
+        fn generate(&self, _: Vec2<i32>) -> Chunk {
+            let mut chunk = Chunk::empty();
+            for x in 0..Chunk::SIZE.x as i32 {
+                for z in 0..Chunk::SIZE.z as i32 {
+                    chunk.set(Vec3::new(x, 0, z), self.stone);
+                }
+            }
+            chunk
+        }
+    }
+
+    fn blocks() -> BlockRegistry {
+        BlockRegistry::from_defs(vec![BlockDef::test("stone")]).unwrap()
+    }
+
+    pub(crate) fn server(save: Option<Arc<WorldSave>>) -> Server {
+        let blocks = blocks();
+        let generator = Flat {
+            stone: blocks.id("stone").unwrap(),
+        };
+        Server::new(
+            ServerConfig::default(),
+            Arc::new(blocks),
+            Arc::new(generator),
+            save,
+        )
+    }
+
+    /// Connects a player over a channel, stepping the server once so that it
+    /// joins.
+    pub(crate) fn join(
+        server: &mut Server,
+        name: &str,
+    ) -> (ChannelConnection<ClientMessage, ServerMessage>, ClientId) {
+        let (server_end, mut client) = ChannelConnection::pair(["server", name]);
+        server.network_mut().add_connection(Box::new(server_end));
+        client
+            .send(ClientMessage::Handshake {
+                protocol_version: PROTOCOL_VERSION,
+                name: name.to_owned(),
+            })
+            .unwrap();
+        server.step();
+        let id = server
+            .network()
+            .sessions()
+            .find(|session| session.name() == name)
+            .unwrap()
+            .id();
+        (client, id)
+    }
+
+    #[test]
+    fn steps_count_ticks() {
+        let mut server = server(None);
+        assert_eq!(server.tick(), 0);
+        for tick in 1..=3 {
+            server.step();
+            assert_eq!(server.tick(), tick);
+        }
+        let stats = server.stats();
+        assert_eq!(stats.ticks, 3);
+        assert!(stats.last_duration < server.tick_interval());
+        assert_eq!(stats.overruns, 0);
+        // Only measured when running on its own thread.
+        assert_eq!(stats.tps, 0.0);
+    }
+
+    #[test]
+    fn stop_command_ends_the_loop() {
+        let handle = server(None).start();
+        assert_eq!(handle.execute("stop").unwrap(), "Stopping the server");
+        let server = handle.join();
+        assert!(server.tick() >= 1);
+    }
+
+    #[test]
+    fn stopping_saves_the_world() {
+        let dir = std::env::temp_dir().join(format!("explora-server-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let pos = Vec3::new(-3, 10, 4);
+        let chunk_pos = World::chunk_pos(pos);
+
+        let blocks = blocks();
+        let (save, _) = WorldSave::open(&dir, 0, &blocks).unwrap();
+        let mut server = server(Some(Arc::new(save)));
+        let stone = server.blocks().id("stone").unwrap();
+        // The chunk is in view of the player, so it is not unloaded, and
+        // saved, before the server stops.
+        let (_client, id) = join(&mut server, "alice");
+        assert!(server
+            .player(id)
+            .unwrap()
+            .interest
+            .in_view(Vec2::zero(), chunk_pos));
+        server.load_chunk(chunk_pos);
+        assert_eq!(server.set_block(pos, stone), Some(BlockId::AIR));
+
+        let server = server.start().stop();
+        assert!(server.world().contains_chunk(chunk_pos));
+        assert_eq!(server.world().modified().count(), 0);
+        drop(server);
+
+        let (save, _) = WorldSave::open(&dir, 0, &blocks).unwrap();
+        let chunk = save.load_chunk(chunk_pos).unwrap().unwrap();
+        assert_eq!(chunk.get(World::local_pos(pos)), Some(stone));
+        drop(save);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,574 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    io,
+    net::{SocketAddr, TcpListener, ToSocketAddrs},
+    time::{Duration, Instant},
+};
+
+use common::{
+    net::{ServerConnection, TcpConnection},
+    protocol::{ClientMessage, ProtocolError, ServerMessage, PROTOCOL_VERSION},
+};
+
+/// Time a new connection has to send its handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Time between two keep-alives sent to a client.
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(5);
+/// Clients that do not send anything for this long are disconnected.
+const TIMEOUT: Duration = Duration::from_secs(30);
+/// Longest player name accepted.
+const MAX_NAME_LEN: usize = 32;
+
+/// Identifies a client for as long as the server runs.
+pub type ClientId = u64;
+
+/// Changes to the set of connected clients.
+#[derive(Debug)]
+pub enum NetworkEvent {
+    /// A client completed its handshake.
+    Joined(ClientId),
+    /// A client was disconnected. Its session is no longer available.
+    Left { id: ClientId, reason: String },
+}
+
+/// A client that completed its handshake.
+pub struct Session {
+    id: ClientId,
+    name: String,
+    connection: Box<ServerConnection>,
+    /// Messages received and not handled yet.
+    incoming: VecDeque<ClientMessage>,
+    /// Messages waiting to be handed to the connection.
+    outgoing: VecDeque<ServerMessage>,
+    last_received: Instant,
+    last_keep_alive: Instant,
+    next_keep_alive: u64,
+}
+
+impl Session {
+    pub fn id(&self) -> ClientId {
+        self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Describes the other end of the connection, for logging.
+    pub fn peer(&self) -> String {
+        self.connection.peer()
+    }
+
+    /// Queues a message, to be sent at the end of the tick.
+    pub fn send(&mut self, message: ServerMessage) {
+        self.outgoing.push_back(message);
+    }
+
+    /// Takes the next message received from the client.
+    pub fn recv(&mut self) -> Option<ClientMessage> {
+        self.incoming.pop_front()
+    }
+}
+
+/// A connection that has not completed its handshake yet.
+struct PendingConnection {
+    connection: Box<ServerConnection>,
+    since: Instant,
+}
+
+/// Accepts connections and keeps track of the connected clients.
+///
+/// All the methods are non-blocking, they are meant to be called once per
+/// tick: [`Network::poll`] first to receive messages, then
+/// [`Network::flush`] to send the messages queued while handling them.
+pub struct Network {
+    listener: Option<TcpListener>,
+    pending: Vec<PendingConnection>,
+    sessions: HashMap<ClientId, Session>,
+    next_id: ClientId,
+    /// Seed sent to clients when they join.
+    seed: u32,
+    /// Block id table sent to clients when they join.
+    blocks: Vec<String>,
+    events: Vec<NetworkEvent>,
+}
+
+impl Network {
+    pub fn new(seed: u32, blocks: Vec<String>) -> Self {
+        Self {
+            listener: None,
+            pending: Vec::new(),
+            sessions: HashMap::new(),
+            next_id: 0,
+            seed,
+            blocks,
+            events: Vec::new(),
+        }
+    }
+
+    /// Starts accepting TCP connections on the given address, returning the
+    /// address actually bound.
+    pub fn listen(&mut self, addr: impl ToSocketAddrs) -> io::Result<SocketAddr> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        let addr = listener.local_addr()?;
+        tracing::info!("Listening on {}", addr);
+        self.listener = Some(listener);
+        Ok(addr)
+    }
+
+    /// Adds an established connection. It has to complete its handshake like
+    /// any other.
+    pub fn add_connection(&mut self, connection: Box<ServerConnection>) {
+        self.pending.push(PendingConnection {
+            connection,
+            since: Instant::now(),
+        });
+    }
+
+    pub fn session(&self, id: ClientId) -> Option<&Session> {
+        self.sessions.get(&id)
+    }
+
+    pub fn session_mut(&mut self, id: ClientId) -> Option<&mut Session> {
+        self.sessions.get_mut(&id)
+    }
+
+    pub fn sessions(&self) -> impl Iterator<Item = &Session> {
+        self.sessions.values()
+    }
+
+    pub fn sessions_mut(&mut self) -> impl Iterator<Item = &mut Session> {
+        self.sessions.values_mut()
+    }
+
+    /// Queues a message to every client.
+    pub fn broadcast(&mut self, message: ServerMessage) {
+        for session in self.sessions.values_mut() {
+            session.send(message.clone());
+        }
+    }
+
+    /// Accepts new connections, completes handshakes and receives the
+    /// messages of every client, returning the clients that joined or left.
+    pub fn poll(&mut self) -> Vec<NetworkEvent> {
+        self.poll_at(Instant::now())
+    }
+
+    /// Like [`Network::poll`], as if it was called at `now`.
+    fn poll_at(&mut self, now: Instant) -> Vec<NetworkEvent> {
+        self.accept();
+        self.poll_pending(now);
+
+        let ids = self.sessions.keys().copied().collect::<Vec<_>>();
+        for id in ids {
+            if let Err(reason) = self.poll_session(id, now) {
+                self.disconnect(id, &reason);
+            }
+        }
+        std::mem::take(&mut self.events)
+    }
+
+    /// Hands the queued messages of every client to their connection.
+    pub fn flush(&mut self) {
+        let mut failed = Vec::new();
+        for session in self.sessions.values_mut() {
+            let result = session
+                .outgoing
+                .drain(..)
+                .try_for_each(|message| session.connection.send(message))
+                .and_then(|()| session.connection.flush());
+            match result {
+                Ok(()) => {}
+                Err(ProtocolError::QueueFull) => {
+                    failed.push((session.id, "Not receiving data fast enough".to_owned()))
+                }
+                Err(e) => failed.push((session.id, format!("Connection error: {:?}", e))),
+            }
+        }
+        for (id, reason) in failed {
+            self.disconnect(id, &reason);
+        }
+    }
+
+    /// Sends the reason to the client and closes its connection.
+    pub fn disconnect(&mut self, id: ClientId, reason: &str) {
+        let Some(mut session) = self.sessions.remove(&id) else {
+            return;
+        };
+        tracing::info!("{} ({}) left: {}", session.name, session.peer(), reason);
+        close(&mut *session.connection, reason);
+        // Clients leaving before their arrival was reported are not reported
+        // at all.
+        let joined = self
+            .events
+            .iter()
+            .position(|event| matches!(event, NetworkEvent::Joined(joined) if *joined == id));
+        match joined {
+            Some(index) => {
+                self.events.remove(index);
+            }
+            None => self.events.push(NetworkEvent::Left {
+                id,
+                reason: reason.to_owned(),
+            }),
+        }
+    }
+
+    /// Disconnects every client, including those still in their handshake.
+    pub fn disconnect_all(&mut self, reason: &str) {
+        for mut pending in self.pending.drain(..) {
+            close(&mut *pending.connection, reason);
+        }
+        let ids = self.sessions.keys().copied().collect::<Vec<_>>();
+        for id in ids {
+            self.disconnect(id, reason);
+        }
+    }
+
+    fn accept(&mut self) {
+        let Some(listener) = &self.listener else {
+            return;
+        };
+        loop {
+            match listener.accept() {
+                Ok((stream, _)) => match TcpConnection::new(stream) {
+                    Ok(connection) => self.pending.push(PendingConnection {
+                        connection: Box::new(connection),
+                        since: Instant::now(),
+                    }),
+                    Err(e) => tracing::warn!("Could not set up a connection: {}", e),
+                },
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    tracing::warn!("Could not accept a connection: {}", e);
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Turns the pending connections that sent a valid handshake into
+    /// sessions, and drops the ones that failed to.
+    fn poll_pending(&mut self, now: Instant) {
+        for mut pending in std::mem::take(&mut self.pending) {
+            let peer = pending.connection.peer();
+            let (protocol_version, name) = match pending.connection.try_recv() {
+                Ok(None) if now - pending.since < HANDSHAKE_TIMEOUT => {
+                    self.pending.push(pending);
+                    continue;
+                }
+                Ok(None) => {
+                    tracing::info!("{} did not send a handshake in time", peer);
+                    close(&mut *pending.connection, "Handshake timed out");
+                    continue;
+                }
+                Ok(Some(ClientMessage::Handshake {
+                    protocol_version,
+                    name,
+                })) => (protocol_version, name),
+                Ok(Some(message)) => {
+                    tracing::info!("{} sent {:?} instead of a handshake", peer, message);
+                    close(&mut *pending.connection, "Expected a handshake");
+                    continue;
+                }
+                Err(e) => {
+                    tracing::info!("{} failed its handshake: {:?}", peer, e);
+                    pending.connection.close();
+                    continue;
+                }
+            };
+
+            let refusal = if protocol_version != PROTOCOL_VERSION {
+                Some(format!(
+                    "Incompatible protocol version: the server uses {}, the client {}",
+                    PROTOCOL_VERSION, protocol_version
+                ))
+            } else if name.is_empty() || name.chars().count() > MAX_NAME_LEN {
+                Some(format!(
+                    "Names must be 1 to {} characters long",
+                    MAX_NAME_LEN
+                ))
+            } else if self.sessions.values().any(|session| session.name == name) {
+                Some(format!("{} is already connected", name))
+            } else {
+                None
+            };
+            if let Some(reason) = refusal {
+                tracing::info!("Refused {} from {}: {}", name, peer, reason);
+                close(&mut *pending.connection, &reason);
+                continue;
+            }
+
+            let id = self.next_id;
+            self.next_id += 1;
+            tracing::info!("{} ({}) joined", name, peer);
+            let mut session = Session {
+                id,
+                name,
+                connection: pending.connection,
+                incoming: VecDeque::new(),
+                outgoing: VecDeque::new(),
+                last_received: now,
+                last_keep_alive: now,
+                next_keep_alive: 0,
+            };
+            session.send(ServerMessage::HandshakeAccepted {
+                seed: self.seed,
+                blocks: self.blocks.clone(),
+            });
+            self.sessions.insert(id, session);
+            self.events.push(NetworkEvent::Joined(id));
+        }
+    }
+
+    /// Receives the messages of a client and keeps its connection alive.
+    ///
+    /// Returns the reason to disconnect the client on failure.
+    fn poll_session(&mut self, id: ClientId, now: Instant) -> Result<(), String> {
+        let session = self.sessions.get_mut(&id).unwrap();
+        loop {
+            match session.connection.try_recv() {
+                Ok(Some(message)) => {
+                    session.last_received = now;
+                    match message {
+                        ClientMessage::Disconnect { reason } => {
+                            return Err(format!("Disconnected by the client: {}", reason))
+                        }
+                        // Handshakes are only valid as the first message.
+                        ClientMessage::Handshake { .. } => {
+                            return Err("Unexpected handshake".to_owned())
+                        }
+                        ClientMessage::KeepAlive { .. } => {}
+                        message => session.incoming.push_back(message),
+                    }
+                }
+                Ok(None) => break,
+                Err(ProtocolError::ConnectionClosed) => return Err("Connection closed".to_owned()),
+                Err(e) => return Err(format!("Connection error: {:?}", e)),
+            }
+        }
+
+        if now - session.last_received > TIMEOUT {
+            return Err("Timed out".to_owned());
+        }
+        if now - session.last_keep_alive > KEEP_ALIVE_INTERVAL {
+            session.last_keep_alive = now;
+            session.send(ServerMessage::KeepAlive {
+                id: session.next_keep_alive,
+            });
+            session.next_keep_alive += 1;
+        }
+        Ok(())
+    }
+}
+
+/// Tells the other end why the connection is being closed, then closes it.
+fn close(connection: &mut ServerConnection, reason: &str) {
+    let _ = connection
+        .send(ServerMessage::Disconnect {
+            reason: reason.to_owned(),
+        })
+        .and_then(|()| connection.flush());
+    connection.close();
+}
+
+#[cfg(test)]
+mod tests {
+    use common::net::Connection;
+
+    use super::*;
+
+    type Client = TcpConnection<ClientMessage, ServerMessage>;
+
+    /// Retries `f` until it returns something, for a few seconds at most.
+    fn wait<T>(mut f: impl FnMut() -> Option<T>) -> T {
+        let start = Instant::now();
+        loop {
+            if let Some(value) = f() {
+                return value;
+            }
+            assert!(start.elapsed() < Duration::from_secs(5), "timed out");
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    fn listen() -> (Network, SocketAddr) {
+        let mut network = Network::new(7, vec!["air".to_owned()]);
+        let addr = network.listen("127.0.0.1:0").unwrap();
+        (network, addr)
+    }
+
+    fn connect(addr: SocketAddr, protocol_version: u32, name: &str) -> Client {
+        let mut client = Client::connect(addr).unwrap();
+        client
+            .send(ClientMessage::Handshake {
+                protocol_version,
+                name: name.to_owned(),
+            })
+            .unwrap();
+        client.flush().unwrap();
+        client
+    }
+
+    fn join(network: &mut Network, addr: SocketAddr, name: &str) -> (Client, ClientId) {
+        let client = connect(addr, PROTOCOL_VERSION, name);
+        let id = wait(|| {
+            network.poll().into_iter().find_map(|event| match event {
+                NetworkEvent::Joined(id) => Some(id),
+                _ => None,
+            })
+        });
+        (client, id)
+    }
+
+    /// Polls the network until the client receives a message.
+    fn recv(network: &mut Network, client: &mut Client) -> ServerMessage {
+        wait(|| {
+            network.poll();
+            network.flush();
+            client.try_recv().unwrap()
+        })
+    }
+
+    fn disconnect_reason(network: &mut Network, client: &mut Client) -> String {
+        match recv(network, client) {
+            ServerMessage::Disconnect { reason } => reason,
+            message => panic!("expected a disconnection, got {:?}", message),
+        }
+    }
+
+    #[test]
+    fn accepts_handshakes() {
+        let (mut network, addr) = listen();
+        let (mut client, id) = join(&mut network, addr, "alice");
+        assert_eq!(network.session(id).unwrap().name(), "alice");
+        match recv(&mut network, &mut client) {
+            ServerMessage::HandshakeAccepted { seed, blocks } => {
+                assert_eq!(seed, 7);
+                assert_eq!(blocks, ["air"]);
+            }
+            message => panic!("expected a handshake answer, got {:?}", message),
+        }
+    }
+
+    #[test]
+    fn clients_leaving_right_after_joining_are_not_players() {
+        let mut server = crate::tests::server(None);
+        let addr = server.listen("127.0.0.1:0").unwrap();
+        let mut client = Client::connect(addr).unwrap();
+        client
+            .send(ClientMessage::Handshake {
+                protocol_version: PROTOCOL_VERSION,
+                name: "alice".to_owned(),
+            })
+            .unwrap();
+        client
+            .send(ClientMessage::Disconnect {
+                reason: "bye".to_owned(),
+            })
+            .unwrap();
+        client.flush().unwrap();
+        client.close();
+        // Let both messages arrive, so the server reads them in a single poll.
+        std::thread::sleep(Duration::from_millis(100));
+
+        server.step();
+        assert!(server.network().pending.is_empty());
+        assert_eq!(server.network().sessions().count(), 0);
+        assert!(server.player(0).is_none());
+    }
+
+    #[test]
+    fn refuses_other_protocol_versions() {
+        let (mut network, addr) = listen();
+        let mut client = connect(addr, PROTOCOL_VERSION + 1, "alice");
+        let reason = disconnect_reason(&mut network, &mut client);
+        assert!(
+            reason.contains("Incompatible protocol version"),
+            "{}",
+            reason
+        );
+        assert_eq!(network.sessions().count(), 0);
+    }
+
+    #[test]
+    fn refuses_duplicate_names() {
+        let (mut network, addr) = listen();
+        let (_alice, _) = join(&mut network, addr, "alice");
+        let mut client = connect(addr, PROTOCOL_VERSION, "alice");
+        let reason = disconnect_reason(&mut network, &mut client);
+        assert_eq!(reason, "alice is already connected");
+        assert_eq!(network.sessions().count(), 1);
+    }
+
+    #[test]
+    fn times_out_handshakes() {
+        let (mut network, addr) = listen();
+        let mut client = Client::connect(addr).unwrap();
+        wait(|| {
+            network.poll();
+            (!network.pending.is_empty()).then_some(())
+        });
+        network.poll_at(Instant::now() + HANDSHAKE_TIMEOUT + Duration::from_secs(1));
+        assert!(network.pending.is_empty());
+        let reason = wait(|| match client.try_recv().unwrap() {
+            Some(ServerMessage::Disconnect { reason }) => Some(reason),
+            _ => None,
+        });
+        assert_eq!(reason, "Handshake timed out");
+    }
+
+    #[test]
+    fn keep_alive_replies_keep_sessions_alive() {
+        let (mut network, addr) = listen();
+        let (mut client, id) = join(&mut network, addr, "alice");
+        let start = Instant::now();
+
+        network.poll_at(start + KEEP_ALIVE_INTERVAL + Duration::from_secs(1));
+        network.flush();
+        let keep_alive = wait(|| match client.try_recv().unwrap() {
+            Some(ServerMessage::KeepAlive { id }) => Some(id),
+            _ => None,
+        });
+        client
+            .send(ClientMessage::KeepAlive { id: keep_alive })
+            .unwrap();
+        client.flush().unwrap();
+
+        // The reply is received later on, which delays the timeout.
+        let replied = start + Duration::from_secs(20);
+        wait(|| {
+            network.poll_at(replied);
+            (network.session(id).unwrap().last_received == replied).then_some(())
+        });
+        let events = network.poll_at(start + TIMEOUT + Duration::from_secs(1));
+        assert!(events.is_empty(), "{:?}", events);
+        assert!(network.session(id).is_some());
+
+        // Without more replies the client times out eventually.
+        let events = network.poll_at(replied + TIMEOUT + Duration::from_secs(1));
+        assert!(matches!(
+            &events[..],
+            [NetworkEvent::Left { id: left, reason }] if *left == id && reason == "Timed out"
+        ));
+    }
+
+    #[test]
+    fn disconnects_clients_not_reading() {
+        let (mut network, addr) = listen();
+        let (_client, id) = join(&mut network, addr, "alice");
+        let message = "x".repeat(60 * 1024);
+        let reason = wait(|| {
+            network.session_mut(id)?.send(ServerMessage::Chat {
+                message: message.clone(),
+            });
+            network.flush();
+            network.poll().into_iter().find_map(|event| match event {
+                NetworkEvent::Left { reason, .. } => Some(reason),
+                _ => None,
+            })
+        });
+        assert_eq!(reason, "Not receiving data fast enough");
+    }
+}
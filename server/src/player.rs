@@ -0,0 +1,30 @@
+use common::{
+    math::{Vec2, Vec3},
+    world::World,
+};
+
+use crate::interest::Interest;
+
+/// Where players appear when they join.
+pub const SPAWN_POS: Vec3<f32> = Vec3::new(0.0, 100.0, 0.0);
+
+/// The state the server keeps for each connected player.
+pub struct Player {
+    pub pos: Vec3<f32>,
+    /// Chunks the client has.
+    pub interest: Interest,
+}
+
+impl Player {
+    pub fn new(pos: Vec3<f32>, view_distance: i32, unload_margin: i32) -> Self {
+        Self {
+            pos,
+            interest: Interest::new(view_distance, unload_margin),
+        }
+    }
+
+    /// The chunk column the player is in.
+    pub fn chunk_pos(&self) -> Vec2<i32> {
+        World::chunk_pos(self.pos.map(|x| x.floor() as i32))
+    }
+}
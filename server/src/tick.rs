@@ -0,0 +1,70 @@
+use std::time::Duration;
+
+/// Weight of the latest tick in the running averages.
+const SMOOTHING: f64 = 0.1;
+
+/// Timing information about the ticks run by the server.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TickStats {
+    /// Number of ticks run so far.
+    pub ticks: u64,
+    /// Time spent running the last tick.
+    pub last_duration: Duration,
+    /// Running average of the time spent per tick.
+    pub mean_duration: Duration,
+    /// Running average of the ticks run per second. Only measured when the
+    /// server runs on its own, see [crate::Server::start].
+    pub tps: f64,
+    /// Number of ticks that took longer than the tick interval.
+    pub overruns: u64,
+}
+
+impl TickStats {
+    /// Accounts for a tick that took `duration`.
+    pub(crate) fn record_tick(&mut self, duration: Duration, interval: Duration) {
+        self.ticks += 1;
+        self.last_duration = duration;
+        self.mean_duration = if self.ticks == 1 {
+            duration
+        } else {
+            self.mean_duration.mul_f64(1.0 - SMOOTHING) + duration.mul_f64(SMOOTHING)
+        };
+        if duration > interval {
+            self.overruns += 1;
+        }
+    }
+
+    /// Accounts for `elapsed` time between the start of two ticks.
+    pub(crate) fn record_interval(&mut self, elapsed: Duration) {
+        let tps = 1.0 / elapsed.as_secs_f64().max(f64::EPSILON);
+        self.tps = if self.tps == 0.0 {
+            tps
+        } else {
+            self.tps * (1.0 - SMOOTHING) + tps * SMOOTHING
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stats_average_ticks() {
+        let interval = Duration::from_millis(50);
+        let mut stats = TickStats::default();
+        stats.record_tick(Duration::from_millis(30), interval);
+        assert_eq!(stats.mean_duration, Duration::from_millis(30));
+        stats.record_tick(Duration::from_millis(70), interval);
+        assert_eq!(stats.ticks, 2);
+        assert_eq!(stats.last_duration, Duration::from_millis(70));
+        assert_eq!(stats.overruns, 1);
+        let mean = stats.mean_duration.as_secs_f64();
+        assert!((mean - 0.034).abs() < 1e-9, "{}", mean);
+
+        stats.record_interval(Duration::from_millis(50));
+        assert!((stats.tps - 20.0).abs() < 1e-9);
+        stats.record_interval(Duration::from_millis(100));
+        assert!((stats.tps - 19.0).abs() < 1e-9);
+    }
+}